@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use crate::spectrum::{self, Window};
+
+// half width, in bins, of the band summed around a tone. wide enough for the
+// main lobe of the blackman-harris window used below.
+const TONE_HALF_WIDTH: usize = 5;
+
+// bins at the bottom of the spectrum that are treated as dc and never counted
+const DC_BINS: usize = 3;
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, s| s.abs().max(peak))
+}
+
+/// Amplitude and phase, in radians, of the component of `samples` at `freq`,
+/// taken as a cosine starting at the first sample. A lock-in detector with a
+/// Hann window, so a whole number of cycles isn't needed.
+pub fn lock_in(samples: &[f32], sample_rate: f32, freq: f32) -> (f32, f32) {
+    let w = Window::Hann.coefficients(samples.len());
+    let omega = 2.0 * std::f64::consts::PI * freq as f64 / sample_rate as f64;
+    let (mut re, mut im) = (0.0, 0.0);
+    for (i, (s, w)) in samples.iter().zip(&w).enumerate() {
+        let x = (*s * *w) as f64;
+        re += x * (omega * i as f64).cos();
+        im -= x * (omega * i as f64).sin();
+    }
+    let gain: f64 = w.iter().map(|w| *w as f64).sum();
+    if gain == 0.0 {
+        return (0.0, 0.0);
+    }
+    ((2.0 * re.hypot(im) / gain) as f32, im.atan2(re) as f32)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Distortion {
+    pub fundamental_hz: f32,
+    /// fundamental level in dBFS
+    pub fundamental_db: f32,
+    /// harmonic distortion, as a ratio to the fundamental amplitude
+    pub thd: f32,
+    /// harmonic distortion plus noise, as a ratio to the fundamental amplitude
+    pub thd_n: f32,
+    pub sinad_db: f32,
+}
+
+impl Distortion {
+    pub fn thd_db(&self) -> f32 {
+        spectrum::to_db(self.thd)
+    }
+
+    pub fn thd_n_db(&self) -> f32 {
+        spectrum::to_db(self.thd_n)
+    }
+}
+
+/// Sum of bin powers within `half_width` bins of `center`.
+fn band_power(power: &[f32], center: usize, half_width: usize) -> f32 {
+    let lo = center.saturating_sub(half_width).max(DC_BINS);
+    let hi = (center + half_width).min(power.len() - 1);
+    if lo > hi {
+        return 0.0;
+    }
+    power[lo..=hi].iter().sum()
+}
+
+/// Index of the largest bin at or above `DC_BINS`.
+pub fn peak_bin(power: &[f32]) -> usize {
+    power.iter().enumerate()
+        .skip(DC_BINS)
+        .fold((DC_BINS, 0.0), |best, (i, p)| if *p > best.1 { (i, *p) } else { best })
+        .0
+}
+
+/// Measure THD (harmonics 2..=`harmonics`), THD+N and SINAD of a single tone.
+/// If `fundamental` is None, the largest spectral peak is used. Returns None if
+/// there is no fundamental to measure against, as from a silent input.
+pub fn distortion(samples: &[f32], sample_rate: f32, fundamental: Option<f32>, harmonics: usize) -> Option<Distortion> {
+    let n = samples.len();
+    let ampl = spectrum::amplitude_spectrum(samples, Window::BlackmanHarris);
+    if ampl.len() <= DC_BINS + 1 {
+        return None;
+    }
+    let power: Vec<f32> = ampl.iter().map(|a| a * a).collect();
+    let bin_hz = sample_rate / (n as f32);
+
+    let fund_bin = match fundamental {
+        Some(f) => {
+            // look for the actual peak near the expected frequency
+            let expected = (f / bin_hz).round() as usize;
+            let lo = expected.saturating_sub(TONE_HALF_WIDTH).max(DC_BINS);
+            let hi = (expected + TONE_HALF_WIDTH).min(power.len() - 1);
+            (lo..=hi).fold(lo, |best, i| if power[i] > power[best] { i } else { best })
+        },
+        None => peak_bin(&power),
+    };
+
+    // parabolic interpolation on the log magnitude for a finer frequency estimate
+    let mut fund_pos = fund_bin as f32;
+    if fund_bin > 0 && fund_bin + 1 < ampl.len() {
+        let a = spectrum::to_db(ampl[fund_bin - 1]);
+        let b = spectrum::to_db(ampl[fund_bin]);
+        let c = spectrum::to_db(ampl[fund_bin + 1]);
+        let denom = a - 2.0 * b + c;
+        if denom != 0.0 {
+            fund_pos += 0.5 * (a - c) / denom;
+        }
+    }
+
+    let fund_power = band_power(&power, fund_bin, TONE_HALF_WIDTH);
+    // the ratios below would be 0/0, or measured against rounding error
+    if spectrum::to_db(fund_power.sqrt()) <= spectrum::DB_FLOOR {
+        return None;
+    }
+    // sum what's outside the fundamental directly; subtracting it from the
+    // total loses everything below about -70 dB to rounding
+    let fund_lo = fund_bin.saturating_sub(TONE_HALF_WIDTH);
+    let fund_hi = fund_bin + TONE_HALF_WIDTH;
+    let residual: f32 = power.iter().enumerate()
+        .skip(DC_BINS)
+        .filter(|(i, _)| *i < fund_lo || *i > fund_hi)
+        .map(|(_, p)| p)
+        .sum();
+    let total_power = fund_power + residual;
+
+    let mut harm_power = 0.0;
+    for h in 2..=harmonics {
+        let bin = (fund_pos * h as f32).round() as usize;
+        if bin + TONE_HALF_WIDTH >= power.len() {
+            break;
+        }
+        harm_power += band_power(&power, bin, TONE_HALF_WIDTH);
+    }
+
+    Some(Distortion {
+        fundamental_hz: fund_pos * bin_hz,
+        fundamental_db: spectrum::to_db((fund_power / Window::BlackmanHarris.enbw(n)).sqrt()),
+        thd: (harm_power / fund_power).sqrt(),
+        thd_n: (residual / fund_power).sqrt(),
+        // a residual of nothing, as from a digital loopback, stops at the dB floor rather than inf
+        sinad_db: -spectrum::to_db((residual / total_power).sqrt()),
+    })
+}
+
+/// Two-tone intermodulation tests: SMPTE RP120, DIN 45403 and CCIF (ITU-R, IEC 60268-3).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Imd {
+    Smpte,
+    Din,
+    Ccif,
+}
+
+impl Imd {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "smpte" => Ok(Imd::Smpte),
+            "din" => Ok(Imd::Din),
+            "ccif" | "itu" => Ok(Imd::Ccif),
+            _ => Err(anyhow!("unknown imd test {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Imd::Smpte => "SMPTE",
+            Imd::Din => "DIN",
+            Imd::Ccif => "CCIF",
+        }
+    }
+
+    /// Low and high tone in Hz, and the amplitude of the low tone relative to the high.
+    pub fn tones(&self) -> (f32, f32, f32) {
+        match self {
+            Imd::Smpte => (60.0, 7000.0, 4.0),
+            Imd::Din => (250.0, 8000.0, 4.0),
+            Imd::Ccif => (19000.0, 20000.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Intermod {
+    /// second order products, as a ratio
+    pub d2: f32,
+    /// third order products, as a ratio
+    pub d3: f32,
+}
+
+impl Intermod {
+    /// both orders together
+    pub fn imd(&self) -> f32 {
+        self.d2.hypot(self.d3)
+    }
+
+    pub fn imd_db(&self) -> f32 {
+        spectrum::to_db(self.imd())
+    }
+
+    pub fn d2_db(&self) -> f32 {
+        spectrum::to_db(self.d2)
+    }
+
+    pub fn d3_db(&self) -> f32 {
+        spectrum::to_db(self.d3)
+    }
+}
+
+/// Measure the intermodulation products of a two-tone test.
+///
+/// For SMPTE and DIN, the sidebands either side of the high tone at the low
+/// tone's frequency (second order) and twice it (third order), relative to the
+/// high tone. For CCIF, the difference tone (second order) and the two products
+/// either side of the pair (third order), relative to the sum of the two tones.
+pub fn intermodulation(samples: &[f32], sample_rate: f32, test: Imd) -> Intermod {
+    let n = samples.len();
+    let ampl = spectrum::amplitude_spectrum(samples, Window::BlackmanHarris);
+    if ampl.len() <= DC_BINS + 1 {
+        return Intermod::default();
+    }
+    let power: Vec<f32> = ampl.iter().map(|a| a * a).collect();
+    let bin_hz = sample_rate / (n as f32);
+    // amplitude of the tone at `freq`, or 0 if it's off the end of the spectrum
+    let tone = |freq: f32| {
+        let bin = (freq / bin_hz).round();
+        if bin < 0.0 || bin as usize + TONE_HALF_WIDTH >= power.len() {
+            return 0.0;
+        }
+        band_power(&power, bin as usize, TONE_HALF_WIDTH).sqrt()
+    };
+
+    let (f1, f2, _) = test.tones();
+    match test {
+        Imd::Smpte | Imd::Din => {
+            let carrier = tone(f2);
+            let sidebands = |k: f32| tone(f2 - k * f1).hypot(tone(f2 + k * f1));
+            Intermod { d2: sidebands(1.0) / carrier, d3: sidebands(2.0) / carrier }
+        },
+        Imd::Ccif => {
+            let sum = tone(f1) + tone(f2);
+            Intermod {
+                d2: tone(f2 - f1) / sum,
+                d3: (tone(2.0 * f1 - f2) + tone(2.0 * f2 - f1)) / sum,
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const FS: f32 = 48000.0;
+
+    // one second of sines, (freq, ampl)
+    fn sines(parts: &[(f64, f64)]) -> Vec<f32> {
+        (0..FS as usize)
+            .map(|i| parts.iter().map(|(f, a)| a * (2.0 * PI * f * i as f64 / FS as f64).sin()).sum::<f64>() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn distortion_of_a_known_second_harmonic() {
+        // -40 dB of second harmonic on a -6 dBFS fundamental
+        let d = distortion(&sines(&[(997.0, 0.5), (1994.0, 0.005)]), FS, None, 5).unwrap();
+        assert!((d.fundamental_hz - 997.0).abs() < 0.5, "{} Hz", d.fundamental_hz);
+        assert!((d.fundamental_db + 6.02).abs() < 0.1, "{} dBFS", d.fundamental_db);
+        assert!((d.thd_db() + 40.0).abs() < 0.1, "THD {} dB", d.thd_db());
+        assert!((d.thd_n_db() + 40.0).abs() < 0.1, "THD+N {} dB", d.thd_n_db());
+        assert!((d.sinad_db - 40.0).abs() < 0.1, "SINAD {} dB", d.sinad_db);
+    }
+
+    #[test]
+    fn intermodulation_of_known_sidebands() {
+        // SMPTE: sidebands 60 Hz either side of the 7 kHz tone for d2, 120 Hz for d3
+        let x = sines(&[(60.0, 0.8), (7000.0, 0.2), (6940.0, 0.001), (7060.0, 0.001), (6880.0, 0.0002), (7120.0, 0.0002)]);
+        let m = intermodulation(&x, FS, Imd::Smpte);
+        assert!((m.d2_db() - spectrum::to_db(0.001f32.hypot(0.001) / 0.2)).abs() < 0.1, "d2 {} dB", m.d2_db());
+        assert!((m.d3_db() - spectrum::to_db(0.0002f32.hypot(0.0002) / 0.2)).abs() < 0.1, "d3 {} dB", m.d3_db());
+
+        // CCIF: the 1 kHz difference tone, and 18 and 21 kHz
+        let x = sines(&[(19000.0, 0.25), (20000.0, 0.25), (1000.0, 0.0005), (18000.0, 0.0001), (21000.0, 0.0001)]);
+        let m = intermodulation(&x, FS, Imd::Ccif);
+        assert!((m.d2_db() + 60.0).abs() < 0.1, "d2 {} dB", m.d2_db());
+        assert!((m.d3_db() - spectrum::to_db(0.0002 / 0.5)).abs() < 0.1, "d3 {} dB", m.d3_db());
+    }
+
+    #[test]
+    fn no_distortion_without_a_signal() {
+        assert!(distortion(&[0.0; 48000], FS, None, 5).is_none());
+        assert!(distortion(&[0.0; 48000], FS, Some(1000.0), 5).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+// Reading recordings from WAV, FLAC and headerless PCM files.
+
+use anyhow::{anyhow, Result};
+
+/// Sample encoding of PCM data; little-endian in raw files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PcmFormat {
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl PcmFormat {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "s16" => Ok(PcmFormat::S16),
+            "s24" => Ok(PcmFormat::S24),
+            "s32" => Ok(PcmFormat::S32),
+            "f32" => Ok(PcmFormat::F32),
+            _ => Err(anyhow!("unknown sample format {}", name)),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            PcmFormat::S16 => 2,
+            PcmFormat::S24 => 3,
+            PcmFormat::S32 | PcmFormat::F32 => 4,
+        }
+    }
+
+    fn decode(&self, b: &[u8]) -> f32 {
+        match self {
+            PcmFormat::S16 => int_to_f32(i16::from_le_bytes([b[0], b[1]]) as i32, 16),
+            // shift into the top of an i32 to sign extend
+            PcmFormat::S24 => int_to_f32(i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8, 24),
+            PcmFormat::S32 => int_to_f32(i32::from_le_bytes([b[0], b[1], b[2], b[3]]), 32),
+            PcmFormat::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+// full scale integer to +/-1.0
+fn int_to_f32(s: i32, bits: u32) -> f32 {
+    (s as f64 / (1u64 << (bits - 1)) as f64) as f32
+}
+
+/// A whole recording in memory, as interleaved samples at full scale +/-1.0.
+pub struct AudioFile {
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl AudioFile {
+    /// Read a WAV or FLAC file, going by the extension.
+    pub fn open(path: &str) -> Result<Self> {
+        let ext = std::path::Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        match ext.to_lowercase().as_str() {
+            "wav" | "wave" => Self::wav(path),
+            "flac" => Self::flac(path),
+            _ => Err(anyhow!("{}: not a wav or flac file; give a :format for raw pcm", path)),
+        }
+    }
+
+    pub fn wav(path: &str) -> Result<Self> {
+        let mut reader = hound::WavReader::open(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+            hound::SampleFormat::Int => reader.samples::<i32>()
+                .map(|s| s.map(|s| int_to_f32(s, spec.bits_per_sample as u32)))
+                .collect(),
+        };
+        Ok(Self {
+            channels: spec.channels as usize,
+            sample_rate: spec.sample_rate,
+            samples: samples.map_err(|e| anyhow!("{}: {}", path, e))?,
+        })
+    }
+
+    pub fn flac(path: &str) -> Result<Self> {
+        let mut reader = claxon::FlacReader::open(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        let info = reader.streaminfo();
+        let samples = reader.samples()
+            .map(|s| s.map(|s| int_to_f32(s, info.bits_per_sample)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("{}: {}", path, e))?;
+        Ok(Self {
+            channels: info.channels as usize,
+            sample_rate: info.sample_rate,
+            samples,
+        })
+    }
+
+    /// Headerless interleaved PCM.
+    pub fn raw(path: &str, format: PcmFormat, channels: usize, sample_rate: u32) -> Result<Self> {
+        if channels == 0 {
+            return Err(anyhow!("raw pcm needs a channel count"));
+        }
+        let bytes = std::fs::read(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        // drop any partial frame at the end
+        let frame_bytes = format.bytes() * channels;
+        let len = bytes.len() / frame_bytes * frame_bytes;
+        let samples = bytes[..len].chunks_exact(format.bytes()).map(|b| format.decode(b)).collect();
+        Ok(Self { channels, sample_rate, samples })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1)
+    }
+}
@@ -3,7 +3,7 @@
 use clap::Parser;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    FromSample, Sample, SizedSample, I24, Stream
+    FromSample, Sample, SizedSample
 };
 use lexpr::{
     Value
@@ -11,12 +11,25 @@ use lexpr::{
 use anyhow::{anyhow, Result};
 use ringbuf::{
     traits::{Consumer, Producer, Split, Observer},
-    HeapRb,
+    HeapRb, HeapCons, HeapProd,
 };
 use std::thread;
 use indicatif::{ProgressBar, ProgressStyle};
 use autt::scope::*;
-use std::sync::Arc;
+use autt::spectrum::{self, Window};
+use autt::analysis::{self, Imd};
+use autt::loudness::LoudnessMeter;
+use autt::weighting::{Weighting, WeightingFilter};
+use autt::sweep::{self, Sweep};
+use autt::multitone::{self, Multitone, Phases};
+use autt::correlation;
+use autt::script::{self, Script, for_plist, as_f32, as_db, as_gain, as_usize, as_name, as_bool, as_channel, as_channels};
+use autt::report::{self, Report, Record};
+use autt::audiofile::{AudioFile, PcmFormat};
+use autt::record::WavRecorder;
+use autt::generator::{Generator, Oscillator, Waveform, BandLimit, Noise, NoiseKind};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "sin generator", long_about = None)]
@@ -40,22 +53,110 @@ struct Opt {
     #[arg(long, default_value_t = String::from(""))]
     sinout: String,
 
+    #[arg(long, default_value_t = String::from(""))]
+    play: String,
+
     #[arg(long, default_value_t = String::from(""))]
     input: String,
 
+    /// Meter the input: rms and peak of each channel (the bar shows the first), and the loudness
+    /// (BS.1770 / R128) and true peak of all of them together
     #[arg(long)]
     mon: bool,
 
+    /// Frequency weighting of the --mon and --scope rms: a, c, z or 468
+    #[arg(long, default_value_t = String::from("z"))]
+    weight: String,
+
     #[arg(long, default_value_t = String::from(""))]
     scope: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    thd: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    imd: String,
+
+    /// Compare channels with a reference channel: delay, polarity and phase,
+    /// e.g. "(:ref 0 :ch (1 2) :maxlag 20ms)"
+    #[arg(long, default_value_t = String::from(""))]
+    align: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    ir: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    latency: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    multitone: String,
+
+    /// Drive each output in turn and measure how much leaks into the other
+    /// inputs, e.g. "(:drive (0 1) :ch (0 1) :freqs (100 1kHz 10kHz))"
+    #[arg(long, default_value_t = String::from(""))]
+    crosstalk: String,
+
+    /// Step the --sinout frequency and measure the response at each step, e.g.
+    /// "(:freqs 3 :band (20 20kHz) :ref 0 :ch (1))" for three steps an octave
+    #[arg(long, default_value_t = String::from(""))]
+    freqresp: String,
+
+    #[arg(long, default_value_t = String::from(""))]
+    record: String,
+
+    /// Write every measurement to a file when autt finishes, e.g. "(:file results.xml :format junit)".
+    /// Continuous measurements need a --dur to finish, and report their last, lowest and highest values.
+    #[arg(long, default_value_t = String::from(""))]
+    report: String,
+
+    #[command(subcommand)]
+    cmd: Option<Sub>,
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum Sub {
+    /// Run a script of autt commands
+    Run {
+        /// The script file
+        file: String,
+    },
+}
+
+// silence played ahead of a sweep, so capture is running before it starts
+const SWEEP_PREROLL: f32 = 0.5;
+
+// RMS level of noise unless told otherwise, -12 dBFS, so gaussian peaks stay in range
+const NOISE_AMPL: f32 = 0.25;
+
+// a driven output whose own input is quieter than this, in dBFS, isn't connected
+const CROSSTALK_FLOOR_DB: f32 = -100.0;
+
+// most points drawn on a scope trace; longer ones keep the min and max of each stretch
+const SCOPE_MAX_POINTS: usize = 2000;
+// samples in the scope's spectrum
+const SCOPE_FFT_LEN: usize = 4096;
+
 #[derive(Clone)]
 struct CmdSinout {
     freq: f32,
     ampl: f32,
     channels: Vec<f32>,
     dur: f32, // 0 = indefinite
+    sweep: Option<(f32, f32)>, // exponential sweep start and end frequency, over dur
+    burst: Option<Burst>, // repeat a burst every `period` instead of the sine
+    period: f32,
+    wave: Waveform,
+    duty: f32, // of a pulse wave
+    band_limit: BandLimit,
+    noise: Option<NoiseKind>, // noise instead of the periodic wave, ampl is then the RMS level (NOISE_AMPL by default)
+    band: (f32, f32), // cutoffs of band limited noise, or the range of log spaced tones
+    seed: u64,
+    tones: Option<Tones>, // a multitone instead of the periodic wave
+    fft: usize, // multitone period
+    phases: Phases,
+    steps: Option<(Vec<Step>, f32)>, // stepped tones and seconds on each, for frequency response and crosstalk
+    imd: Option<Imd>, // the two tones of an intermodulation test instead of the periodic wave
+    per_channel: Vec<ChannelTone>, // a wave of its own on each of these channels, instead of one shared
 }
 
 impl CmdSinout {
@@ -65,19 +166,135 @@ impl CmdSinout {
             ampl: 1.0,
             channels: Vec::new(),
             dur: 0.0,
+            sweep: None,
+            burst: None,
+            period: 0.5,
+            wave: Waveform::Sine,
+            duty: 0.5,
+            band_limit: BandLimit::default(),
+            noise: None,
+            band: (20.0, 20000.0),
+            seed: 0,
+            tones: None,
+            fft: 16384,
+            phases: Phases::default(),
+            steps: None,
+            imd: None,
+            per_channel: Vec::new(),
+        }
+    }
+
+    // plays the one periodic wave, rather than a sweep, burst, noise or set of tones
+    fn periodic(&self) -> bool {
+        self.sweep.is_none() && self.burst.is_none() && self.noise.is_none() && self.tones.is_none() && self.imd.is_none()
+    }
+
+    fn multitone(&self, sample_rate: f32) -> Result<Option<Multitone>> {
+        let Some(tones) = &self.tones else {
+            return Ok(None);
+        };
+        Multitone::new(&tones.freqs(self.band), self.fft, sample_rate, self.phases, self.seed).map(Some)
+    }
+}
+
+// One step of a stepped stimulus.
+#[derive(Clone)]
+struct Step {
+    freq: f32,
+    gains: Vec<f32>, // per output channel; empty = the sinout's own
+}
+
+// One output channel's wave, when channels don't all share the same one.
+#[derive(Clone)]
+struct ChannelTone {
+    ch: u8,
+    freq: f32,
+    ampl: f32,
+    phase: f32, // degrees
+    on: bool,
+}
+
+#[derive(Clone)]
+enum Tones {
+    List(Vec<f32>),
+    PerOctave(f32), // log spaced across the band
+}
+
+impl Tones {
+    fn parse(val: &Value) -> Result<Self> {
+        Ok(match val.list_iter() {
+            Some(i) => Tones::List(i.map(as_f32).collect::<Result<_>>()?),
+            None => Tones::PerOctave(as_f32(val)?),
+        })
+    }
+
+    fn freqs(&self, band: (f32, f32)) -> Vec<f32> {
+        match self {
+            Tones::List(freqs) => freqs.clone(),
+            Tones::PerOctave(n) => multitone::log_spaced(band.0, band.1, *n),
+        }
+    }
+}
+
+// parse a (low high) pair of frequencies
+fn parse_band(val: &Value) -> Result<(f32, f32)> {
+    let f: Vec<f32> = match val.list_iter() {
+        Some(i) => i.map(as_f32).collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+    let [lo, hi] = f[..] else {
+        return Err(anyhow!("expected (low high) frequencies"));
+    };
+    if lo >= hi {
+        return Err(anyhow!("band low frequency must be below the high"));
+    }
+    Ok((lo, hi))
+}
+
+// A file channel sent to a device channel.
+#[derive(Clone, Copy)]
+struct Route {
+    from: usize,
+    to: usize,
+    gain: f32,
+}
+
+#[derive(Clone)]
+struct CmdPlay {
+    file: String,
+    looped: bool,
+    routes: Vec<Route>, // empty = file channel n to device channel n
+}
+
+impl CmdPlay {
+    fn new() -> Self {
+        Self {
+            file: String::new(),
+            looped: false,
+            routes: Vec::new(),
         }
     }
 }
 
 #[derive(Clone)]
 struct CmdInput {
-    channels: Vec<u8>,
+    channels: Vec<u8>, // empty = every channel
+    file: Option<String>, // read a recording instead of the input device
+    realtime: bool, // play the file at its own rate, rather than as fast as it's consumed
+    raw: Option<PcmFormat>, // headerless pcm, with:
+    raw_channels: usize,
+    raw_rate: u32,
 }
 
 impl CmdInput {
     fn new() -> Self {
         Self {
-            channels: Vec::new()
+            channels: Vec::new(),
+            file: None,
+            realtime: true,
+            raw: None,
+            raw_channels: 0,
+            raw_rate: 48000,
         }
     }
 }
@@ -85,297 +302,2001 @@ impl CmdInput {
 #[derive(Clone)]
 struct CmdScope {
     channels: Vec<u8>,
+    window: Window,
+    spectrogram: Option<SpectrogramConfig>,
+    trigger: Trigger,
+    trigger_ch: Option<u8>, // device channel; None = the first shown
+    timebase: f32, // seconds per division
+    view: View, // every channel starts with this
+    xy: XyConfig,
+    xy_ch: Option<Vec<u8>>, // device channels; None = the first two shown
 }
 
 impl CmdScope {
     fn new() -> Self {
         Self {
-            channels: Vec::new()
+            channels: Vec::new(),
+            window: Window::default(),
+            spectrogram: None,
+            trigger: Trigger::default(),
+            trigger_ch: None,
+            timebase: 1e-3,
+            view: View::default(),
+            xy: XyConfig::default(),
+            xy_ch: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdThd {
+    channels: Vec<u8>,
+    harmonics: usize,
+    freq: Option<f32>, // None = use the largest peak
+}
+
+impl CmdThd {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            harmonics: 10,
+            freq: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdAlign {
+    reference: u8,
+    channels: Vec<u8>, // compared with the reference; empty = all the others
+    max_lag: f32, // seconds either way
+}
+
+impl CmdAlign {
+    fn new() -> Self {
+        Self {
+            reference: 0,
+            channels: Vec::new(),
+            max_lag: 0.02,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdImd {
+    channels: Vec<u8>,
+    test: Option<Imd>, // None = the test sinout is playing
+}
+
+impl CmdImd {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            test: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Burst {
+    Impulse,
+    Mls,
+}
+
+impl Burst {
+    fn samples(&self) -> Result<Vec<f32>> {
+        match self {
+            Burst::Impulse => Ok(vec![1.0]),
+            Burst::Mls => correlation::mls(12),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdFreqresp {
+    tones: Tones,
+    band: (f32, f32),
+    settle: f32, // seconds at each step before measuring; must cover the system's latency
+    dwell: f32, // seconds measured at each step
+    reference: Option<u8>, // channel the others are relative to, else the stimulus
+    channels: Vec<u8>,
+    out: String, // output file prefix
+}
+
+impl CmdFreqresp {
+    fn new() -> Self {
+        Self {
+            tones: Tones::PerOctave(3.0),
+            band: (20.0, 20000.0),
+            settle: 0.1,
+            dwell: 0.2,
+            reference: None,
+            channels: Vec::new(),
+            out: String::from("freqresp"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdCrosstalk {
+    drive: Vec<u8>, // output channels, driven in turn; empty = all of them
+    channels: Vec<u8>, // the input each output comes back on; empty = the same numbers
+    tones: Tones,
+    band: (f32, f32),
+    settle: f32, // seconds at each step before measuring; must cover the system's latency
+    dwell: f32, // seconds measured at each step
+    out: String, // output file prefix
+}
+
+impl CmdCrosstalk {
+    fn new() -> Self {
+        Self {
+            drive: Vec::new(),
+            channels: Vec::new(),
+            tones: Tones::List(vec![100.0, 1000.0, 10000.0]),
+            band: (20.0, 20000.0),
+            settle: 0.1,
+            dwell: 0.2,
+            out: String::from("crosstalk"),
+        }
+    }
+
+    // each frequency with each output driven on its own
+    fn steps(&self) -> Vec<Step> {
+        let outs = self.drive.iter().max().map_or(0, |ch| *ch as usize + 1);
+        let mut steps = Vec::new();
+        for freq in self.tones.freqs(self.band) {
+            for ch in &self.drive {
+                let mut gains = vec![0.0; outs];
+                gains[*ch as usize] = 1.0;
+                steps.push(Step { freq, gains });
+            }
+        }
+        steps
+    }
+}
+
+#[derive(Clone)]
+struct CmdMultitone {
+    channels: Vec<u8>,
+    periods: usize, // averaged, after one to settle
+    out: String, // output file prefix
+}
+
+impl CmdMultitone {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            periods: 4,
+            out: String::from("multitone"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CmdLatency {
+    channels: Vec<u8>,
+    reps: usize,
+    max: f32, // longest latency expected, seconds; the burst period must exceed it
+}
+
+impl CmdLatency {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            reps: 10,
+            max: 0.1,
+        }
+    }
+}
+
+// Frame count of a stream at the time of its latest callback. Input and
+// output callbacks are timestamped against the same clock, so this lets us
+// line up positions in the two streams.
+#[derive(Clone, Copy)]
+struct StreamClock {
+    frame: u64,
+    at: cpal::StreamInstant,
+}
+
+type SharedClock = Arc<Mutex<Option<StreamClock>>>;
+
+// signed seconds from `earlier` to `later`
+fn seconds_between(later: &cpal::StreamInstant, earlier: &cpal::StreamInstant) -> f64 {
+    match later.duration_since(earlier) {
+        Some(d) => d.as_secs_f64(),
+        None => -earlier.duration_since(later).unwrap_or_default().as_secs_f64(),
+    }
+}
+
+// The input frames to analyse for step `k` of a stepped stimulus: the step
+// after its first `settle` seconds. None if the capture of `len` frames misses it.
+fn step_window(k: usize, step_frames: u64, settle: f32, clocks: (&StreamClock, &StreamClock), sample_rate: f32, len: usize) -> Option<std::ops::Range<usize>> {
+    let (out_clock, in_clock) = clocks;
+    let start = input_frame_at(k as u64 * step_frames, out_clock, in_clock, sample_rate) + (settle * sample_rate) as f64;
+    let end = input_frame_at((k as u64 + 1) * step_frames, out_clock, in_clock, sample_rate);
+    if start < 0.0 || end as usize > len || end <= start {
+        return None;
+    }
+    Some(start as usize..end as usize)
+}
+
+// input frame position corresponding to output frame `frame`
+fn input_frame_at(frame: u64, out_clock: &StreamClock, in_clock: &StreamClock, sample_rate: f32) -> f64 {
+    let t = (frame as f64 - out_clock.frame as f64) / sample_rate as f64;
+    in_clock.frame as f64 + (t - seconds_between(&in_clock.at, &out_clock.at)) * sample_rate as f64
+}
+
+#[derive(Clone)]
+struct CmdIr {
+    channels: Vec<u8>,
+    out: String, // output file prefix
+    harmonics: usize,
+    len: f32, // linear ir length, seconds
+    tail: f32, // extra capture after the sweep ends, seconds
+}
+
+impl CmdIr {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            out: String::from("sweep"),
+            harmonics: 5,
+            len: 0.5,
+            tail: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Metric {
+    Rms,
+    Peak,
+    Thd,
+    Imd,
+}
+
+#[derive(Clone)]
+struct CmdMeasure {
+    metric: Metric,
+    channels: Vec<u8>, // device input channels; empty = everything captured
+    harmonics: usize,
+    len: f32, // seconds of input to analyse
+    imd: Option<Imd>, // None = the test sinout is playing
+}
+
+impl CmdMeasure {
+    fn new(metric: Metric) -> Self {
+        Self {
+            metric,
+            channels: Vec::new(),
+            harmonics: 10,
+            len: 0.5,
+            imd: None,
+        }
+    }
+}
+
+struct CmdRecord {
+    channels: Vec<u8>, // empty = every channel
+    file: String,
+    dur: f32, // 0 = until autt stops
+    format: PcmFormat,
+    split: bool, // a mono file per channel
+}
+
+impl CmdRecord {
+    fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            file: String::new(),
+            dur: 0.0,
+            format: PcmFormat::F32,
+            split: false,
         }
     }
 }
 
+// A measurement with limits; the script fails if any value is outside them.
+struct CmdExpect {
+    measure: CmdMeasure,
+    metric: String, // which of the measured values to check
+    min: Option<f32>,
+    max: Option<f32>,
+    name: Option<String>,
+}
+
 enum Command {
     Sinout(CmdSinout),
-    Input(CmdInput)
+    Play(CmdPlay),
+    Stop,
+    Input(CmdInput),
+    Measure(CmdMeasure),
+    Expect(CmdExpect),
+    Wait(f32),
 }
 
-fn main() -> anyhow::Result<()> {
-    let opt = Opt::parse();
+// One value from a measurement.
+#[derive(Clone)]
+struct Measurement {
+    ch: u8,
+    metric: &'static str,
+    value: f32,
+    unit: &'static str,
+}
+
+impl Measurement {
+    fn record(&self) -> Record {
+        Record::new(self.ch, self.metric, self.value, self.unit)
+    }
+}
+
+struct CmdReport {
+    file: String,
+    format: report::Format,
+}
 
-    let host = cpal::default_host();
+type SharedReport = Arc<Mutex<Report>>;
 
-    let output_device = if opt.device == "default" {
-        host.default_output_device()
-    } else {
-        host.output_devices()?
-            .find(|x| x.name().map(|y| y == opt.device).unwrap_or(false))
+// The outcome of one expect check on one channel.
+struct Verdict {
+    name: Option<String>,
+    m: Measurement,
+    min: Option<f32>,
+    max: Option<f32>,
+    pass: bool,
+}
+
+impl Verdict {
+    fn record(&self) -> Record {
+        Record {
+            name: self.name.clone(),
+            min: self.min,
+            max: self.max,
+            pass: Some(self.pass),
+            ..self.m.record()
+        }
+    }
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ", if self.pass { "PASS" } else { "FAIL" })?;
+        if let Some(name) = &self.name {
+            write!(f, "{}: ", name)?;
+        }
+        write!(f, "ch{} {} {:.2} {}", self.m.ch, self.m.metric, self.m.value, self.m.unit)?;
+        if let Some(min) = self.min {
+            write!(f, " min {}", min)?;
+        }
+        if let Some(max) = self.max {
+            write!(f, " max {}", max)?;
+        }
+        Ok(())
+    }
+}
+
+// The audio devices and the stream config used for both of them.
+struct Devices {
+    output: cpal::Device,
+    input: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+}
+
+impl Devices {
+    fn open(name: &str) -> Result<Self> {
+        let host = cpal::default_host();
+
+        let output = if name == "default" {
+            host.default_output_device()
+        } else {
+            host.output_devices()?
+                .find(|x| x.name().map(|y| y == name).unwrap_or(false))
+        }
+        .ok_or_else(|| anyhow!("failed to find output device"))?;
+        println!("Output device: {}", output.name()?);
+
+        let input = if name == "default" {
+            host.default_input_device()
+        } else {
+            host.input_devices()?
+                .find(|x| x.name().map(|y| y == name).unwrap_or(false))
+        }
+        .ok_or_else(|| anyhow!("failed to find input device"))?;
+        println!("Input device: {}", input.name()?);
+
+        let config = output.default_output_config()?;
+        println!("Default output config: {config:?}");
+
+        Ok(Self { output, input, config })
+    }
+}
+
+// Feeds a recording into an input ring as if it were arriving from a device.
+struct FileFeed {
+    stop: Arc<AtomicBool>,
+    done: Arc<AtomicBool>,
+}
+
+impl Drop for FileFeed {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// Keeps an input running until dropped.
+enum InputStream {
+    Device { _stream: cpal::Stream },
+    File(FileFeed),
+}
+
+impl InputStream {
+    // a recording has been played and consumed; devices never finish
+    fn finished(&self) -> bool {
+        match self {
+            InputStream::Device { .. } => false,
+            InputStream::File(feed) => feed.done.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// A copy of some of an input's channels, separate from the ring the analysers read.
+struct Tap {
+    channels: Vec<u8>,
+    consumer: HeapCons<f32>,
+    dropped: Arc<AtomicU64>, // frames that didn't fit
+}
+
+// channels to copy, and where to
+struct TapProducer {
+    channels: Vec<u8>,
+    producer: HeapProd<f32>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl TapProducer {
+    // copy the tapped channels of `frame`, or drop all of them if there is no room,
+    // so the channels never slip against each other
+    fn push(&mut self, frame: &[f32]) {
+        if self.producer.vacant_len() < self.channels.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        for ch in &self.channels {
+            let _ = self.producer.try_push(frame[*ch as usize]);
+        }
+    }
+}
+
+fn tap(channels: Vec<u8>) -> (TapProducer, Tap) {
+    // a second's worth at any likely sample rate
+    let (producer, consumer) = HeapRb::<f32>::new(192000 * channels.len()).split();
+    let dropped = Arc::new(AtomicU64::new(0));
+    (TapProducer { channels: channels.clone(), producer, dropped: dropped.clone() }, Tap { channels, consumer, dropped })
+}
+
+struct Input {
+    stream: InputStream,
+    consumer: HeapCons<f32>,
+    channels: Vec<u8>,
+    sample_rate: f32,
+    clock: SharedClock, // never set for files
+    source: String,
+    config: String,
+    tap: Option<Tap>,
+}
+
+impl Input {
+    // `tap` asks for a copy of some channels; empty means all of them
+    fn start(cmd: &CmdInput, devices: Option<&Devices>, warn_overrun: bool, tap_channels: Option<&[u8]>) -> Result<Self> {
+        let all_or = |chs: &[u8], ct: usize| if chs.is_empty() { (0..ct as u8).collect() } else { chs.to_vec() };
+        if let Some(path) = &cmd.file {
+            let file = match cmd.raw {
+                Some(format) => AudioFile::raw(path, format, cmd.raw_channels, cmd.raw_rate)?,
+                None => AudioFile::open(path)?,
+            };
+            println!("Input file: {} ({} ch, {} Hz, {:.1} s)",
+                path, file.channels, file.sample_rate, file.frames() as f32 / file.sample_rate as f32);
+            let channels = all_or(&cmd.channels, file.channels);
+            let (tap_producer, tap) = tap_channels.map(|chs| tap(all_or(chs, file.channels))).unzip();
+            let sample_rate = file.sample_rate as f32;
+            let config = format!("{} ch, {} Hz, file", file.channels, file.sample_rate);
+            let (feed, consumer) = start_file_input(file, &channels, cmd.realtime, tap_producer)?;
+            return Ok(Self {
+                stream: InputStream::File(feed),
+                consumer,
+                channels,
+                sample_rate,
+                clock: Arc::new(Mutex::new(None)),
+                source: path.clone(),
+                config,
+                tap,
+            });
+        }
+
+        let devices = devices.ok_or_else(|| anyhow!("no input device"))?;
+        let config = &devices.config;
+        let channels = all_or(&cmd.channels, config.channels() as usize);
+        let (tap_producer, tap) = tap_channels.map(|chs| tap(all_or(chs, config.channels() as usize))).unzip();
+        let (stream, consumer, clock) = start_input(&devices.input, config, &channels, warn_overrun, tap_producer)?;
+        Ok(Self {
+            stream: InputStream::Device { _stream: stream },
+            consumer,
+            channels,
+            sample_rate: config.sample_rate().0 as f32,
+            clock,
+            source: devices.input.name()?,
+            config: format!("{} ch, {} Hz, {}", config.channels(), config.sample_rate().0, config.sample_format()),
+            tap,
+        })
+    }
+
+    fn report_source(&self, report: &SharedReport) {
+        let mut report = report.lock().unwrap();
+        report.device = self.source.clone();
+        report.config = self.config.clone();
+    }
+}
+
+// Devices and running streams for a script.
+struct Session<'a> {
+    device: &'a str,
+    devices: Option<Devices>, // opened when first needed
+    output: Option<cpal::Stream>,
+    sinout: Option<CmdSinout>,
+    input: Option<Input>,
+    verdicts: Vec<Verdict>,
+    report: SharedReport,
+}
+
+impl Session<'_> {
+    fn exec(&mut self, cmd: Command) -> Result<()> {
+        match cmd {
+            Command::Sinout(params) => {
+                self.output = None;
+                let clock = Arc::new(Mutex::new(None));
+                let devices = self.devices()?;
+                self.output = Some(start_sinout(&devices.output, &devices.config, params.clone(), clock)?);
+                self.sinout = Some(params);
+            },
+            Command::Play(cmd) => {
+                self.output = None;
+                self.sinout = None;
+                let clock = Arc::new(Mutex::new(None));
+                let devices = self.devices()?;
+                self.output = Some(start_play(&devices.output, &devices.config, &cmd, clock)?);
+            },
+            Command::Stop => {
+                self.output = None;
+                self.sinout = None;
+            },
+            Command::Input(cmd) => self.start_input(&cmd)?,
+            Command::Wait(secs) => std::thread::sleep(std::time::Duration::from_secs_f32(secs)),
+            Command::Measure(cmd) => {
+                for m in self.measure(&cmd)? {
+                    println!("ch{} {} {:.2} {}", m.ch, m.metric, m.value, m.unit);
+                    self.report.lock().unwrap().push(m.record());
+                }
+            },
+            Command::Expect(cmd) => {
+                for m in self.measure(&cmd.measure)?.into_iter().filter(|m| m.metric == cmd.metric) {
+                    let pass = cmd.min.is_none_or(|min| m.value >= min) && cmd.max.is_none_or(|max| m.value <= max);
+                    let v = Verdict { name: cmd.name.clone(), m, min: cmd.min, max: cmd.max, pass };
+                    println!("{}", v);
+                    self.report.lock().unwrap().push(v.record());
+                    self.verdicts.push(v);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn devices(&mut self) -> Result<&Devices> {
+        if self.devices.is_none() {
+            self.devices = Some(Devices::open(self.device)?);
+        }
+        Ok(self.devices.as_ref().unwrap())
+    }
+
+    fn start_input(&mut self, cmd: &CmdInput) -> Result<()> {
+        self.input = None;
+        if cmd.file.is_none() {
+            self.devices()?;
+        }
+        let input = Input::start(cmd, self.devices.as_ref(), false, None)?;
+        input.report_source(&self.report);
+        self.input = Some(input);
+        Ok(())
+    }
+
+    fn measure(&mut self, cmd: &CmdMeasure) -> Result<Vec<Measurement>> {
+        // capture everything if the script hasn't said what to capture
+        if self.input.is_none() {
+            self.start_input(&CmdInput::new())?;
+        }
+        let freq = self.sinout.as_ref().map(|p| p.freq);
+        let imd = cmd.imd.or(self.sinout.as_ref().and_then(|p| p.imd));
+        if matches!(cmd.metric, Metric::Imd) && imd.is_none() {
+            return Err(anyhow!("imd needs a :test, or a sinout playing one"));
+        }
+        let input = self.input.as_mut().unwrap();
+        let sample_rate = input.sample_rate;
+
+        let channels = if cmd.channels.is_empty() { input.channels.clone() } else { cmd.channels.clone() };
+        let positions = input_positions(&channels, &input.channels)?;
+
+        // throw away anything captured before now
+        input.consumer.clear();
+        let frames = (cmd.len * sample_rate) as usize;
+        let buf = capture(&mut input.consumer, input.channels.len(), &positions, frames);
+
+        let mut results = Vec::new();
+        for (i, ch) in channels.iter().enumerate() {
+            let samples = deinterleave(&buf, i, channels.len());
+            let mut result = |metric, value, unit| results.push(Measurement { ch: *ch, metric, value, unit });
+            match cmd.metric {
+                Metric::Rms => result("rms", spectrum::to_db(analysis::rms(&samples)), "dBFS"),
+                Metric::Peak => result("peak", spectrum::to_db(analysis::peak(&samples)), "dBFS"),
+                Metric::Thd => {
+                    // fail rather than pass limits on a dead input
+                    let Some(d) = analysis::distortion(&samples, sample_rate, freq, cmd.harmonics) else {
+                        return Err(anyhow!("ch {}: no signal to measure distortion of", ch));
+                    };
+                    result("freq", d.fundamental_hz, "Hz");
+                    result("thd", d.thd_db(), "dB");
+                    result("thd+n", d.thd_n_db(), "dB");
+                    result("sinad", d.sinad_db, "dB");
+                },
+                Metric::Imd => {
+                    let m = analysis::intermodulation(&samples, sample_rate, imd.unwrap());
+                    result("imd", m.imd_db(), "dB");
+                    result("imd2", m.d2_db(), "dB");
+                    result("imd3", m.d3_db(), "dB");
+                },
+            }
+        }
+        Ok(results)
+    }
+}
+
+// Writes an input tap to WAV on its own thread. Dropping it stops the
+// recording and finishes the files.
+struct Recording {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<WavRecorder>>>,
+    sample_rate: f32,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Recording {
+    fn start(cmd: &CmdRecord, tap: Tap, sample_rate: f32) -> Result<Self> {
+        let mut recorder = WavRecorder::create(&cmd.file, &tap.channels, sample_rate as u32, cmd.format, cmd.split)?;
+        let limit = (cmd.dur > 0.0).then_some((cmd.dur * sample_rate) as u64);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_t = stop.clone();
+        let Tap { channels, mut consumer, dropped } = tap;
+        let thread = thread::spawn(move || {
+            let mut frame = vec![0.0; channels.len()];
+            while limit.is_none_or(|l| recorder.frames() < l) {
+                if consumer.occupied_len() >= frame.len() {
+                    consumer.pop_slice(&mut frame);
+                    recorder.write(&frame)?;
+                } else if stop_t.load(Ordering::Relaxed) {
+                    break;
+                } else {
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                }
+            }
+            Ok(recorder)
+        });
+        Ok(Self { stop, thread: Some(thread), sample_rate, dropped })
+    }
+
+    // the recording has reached its duration
+    fn finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let Some(thread) = self.thread.take() else { return };
+        let result = thread.join()
+            .map_err(|_| anyhow!("recording thread panicked"))
+            .and_then(|r| r)
+            .and_then(|recorder| {
+                println!("recorded {:.1} s to {}", recorder.frames() as f32 / self.sample_rate, recorder.paths().join(", "));
+                let dropped = self.dropped.load(Ordering::Relaxed);
+                if dropped > 0 {
+                    eprintln!("--record: dropped {} frames the disk couldn't keep up with", dropped);
+                }
+                recorder.finalize()
+            });
+        if let Err(e) = result {
+            eprintln!("--record: {}", e);
+        }
+    }
+}
+
+fn run_script(path: &str, device: &str, report: SharedReport) -> Result<()> {
+    let script = Script::from_file(path)?;
+    let mut session = Session {
+        device,
+        devices: None,
+        output: None,
+        sinout: None,
+        input: None,
+        verdicts: Vec::new(),
+        report,
+    };
+    let result = script.run(|name, args| session.exec(parse_cmd(name, args)?));
+
+    let verdicts = &session.verdicts;
+    if !verdicts.is_empty() {
+        let failed: Vec<&Verdict> = verdicts.iter().filter(|v| !v.pass).collect();
+        println!("{} checks, {} passed, {} failed", verdicts.len(), verdicts.len() - failed.len(), failed.len());
+        for v in &failed {
+            println!("  {}", v);
+        }
+        if result.is_ok() && !failed.is_empty() {
+            return Err(anyhow!("{} of {} checks failed", failed.len(), verdicts.len()));
+        }
+    }
+    result
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+
+    let report_cmd = if opt.report.is_empty() {
+        None
+    } else {
+        let args = script::parse_args(&opt.report)?;
+        Some(parse_report(&args).map_err(|e| anyhow!("--report: {}", e))?)
+    };
+    // the input fills in where the results come from
+    let report = Arc::new(Mutex::new(Report::new("", "")));
+
+    let result = match &opt.cmd {
+        Some(Sub::Run { file }) => run_script(file, &opt.device, report.clone()),
+        None => run(&opt, report.clone()),
+    };
+
+    if let Some(cmd) = report_cmd {
+        let report = report.lock().unwrap();
+        report.write(&cmd.file, cmd.format)?;
+        println!("wrote {} results to {}", report.records.len(), cmd.file);
+    }
+    result
+}
+
+// Run the stream and analysis options given on the command line.
+fn run(opt: &Opt, report: SharedReport) -> Result<()> {
+    let input_cmd = if opt.input.is_empty() {
+        None
+    } else {
+        let input_args = script::parse_args(&opt.input)?;
+        Some(parse_input(&input_args).map_err(|e| anyhow!("--input: {}", e))?)
+    };
+    // recordings can be analysed without any audio hardware
+    if !opt.sinout.is_empty() && !opt.play.is_empty() {
+        return Err(anyhow!("--sinout and --play can't both drive the output"));
+    }
+    let devices = if opt.sinout.is_empty() && opt.play.is_empty() && input_cmd.as_ref().is_some_and(|c| c.file.is_some()) {
+        None
+    } else {
+        Some(Devices::open(&opt.device)?)
+    };
+
+    let record_cmd = if opt.record.is_empty() {
+        None
+    } else {
+        let args = script::parse_args(&opt.record)?;
+        Some(parse_record(&args).map_err(|e| anyhow!("--record: {}", e))?)
+    };
+
+    let mut crosstalk_cmd = if opt.crosstalk.is_empty() {
+        None
+    } else {
+        if opt.sinout.is_empty() {
+            return Err(anyhow!("--crosstalk needs a --sinout for the tone"));
+        }
+        let args = script::parse_args(&opt.crosstalk)?;
+        Some(parse_crosstalk(&args).map_err(|e| anyhow!("--crosstalk: {}", e))?)
+    };
+    if crosstalk_cmd.is_some() && !opt.freqresp.is_empty() {
+        return Err(anyhow!("--crosstalk and --freqresp can't both step the output"));
+    }
+    let freqresp_cmd = if opt.freqresp.is_empty() {
+        None
+    } else {
+        if opt.sinout.is_empty() {
+            return Err(anyhow!("--freqresp needs a --sinout to step"));
+        }
+        let args = script::parse_args(&opt.freqresp)?;
+        Some(parse_freqresp(&args).map_err(|e| anyhow!("--freqresp: {}", e))?)
+    };
+
+    // streams stop when dropped, so hold on to them until main returns
+    let _output_stream: Option<cpal::Stream>;
+    let mut input_stream: Option<InputStream> = None;
+    let mut recording: Option<Recording> = None;
+    let mut sinout_params: Option<CmdSinout> = None;
+    let output_clock: SharedClock = Arc::new(Mutex::new(None));
+
+    // --- sinout
+    if !opt.sinout.is_empty() {
+        //println!("sinout");
+
+        let sinout_cmd = script::parse_args(&opt.sinout)?;
+
+        let mut params = parse_sinout(&sinout_cmd).map_err(|e| anyhow!("--sinout: {}", e))?;
+        if let Some(cmd) = &freqresp_cmd {
+            if !params.per_channel.is_empty() {
+                return Err(anyhow!("--freqresp steps one wave, not one per channel"));
+            }
+            if !params.periodic() {
+                return Err(anyhow!("--freqresp steps a periodic wave, not a sweep, burst, noise, multitone or imd test"));
+            }
+            let steps = cmd.tones.freqs(cmd.band).into_iter().map(|freq| Step { freq, gains: Vec::new() }).collect();
+            params.steps = Some((steps, cmd.settle + cmd.dwell));
+        }
+        let devices = devices.as_ref().unwrap();
+        if let Some(cmd) = &mut crosstalk_cmd {
+            if !params.per_channel.is_empty() {
+                return Err(anyhow!("--crosstalk drives one wave, not one per channel"));
+            }
+            if !params.periodic() {
+                return Err(anyhow!("--crosstalk drives a periodic wave, not a sweep, burst, noise, multitone or imd test"));
+            }
+            if cmd.drive.is_empty() {
+                cmd.drive = (0..devices.config.channels() as u8).collect();
+            }
+            if let Some(ch) = cmd.drive.iter().find(|ch| **ch as u16 >= devices.config.channels()) {
+                return Err(anyhow!("--crosstalk: ch {} is not an output", ch));
+            }
+            if cmd.channels.is_empty() {
+                cmd.channels = cmd.drive.clone();
+            }
+            if cmd.channels.len() != cmd.drive.len() {
+                return Err(anyhow!("--crosstalk: give an input :ch for each output in :drive"));
+            }
+            params.steps = Some((cmd.steps(), cmd.settle + cmd.dwell));
+        }
+        sinout_params = Some(params.clone());
+
+        _output_stream = Some(start_sinout(&devices.output, &devices.config, params, output_clock.clone())?);
+    }
+
+    // --- play
+    else if !opt.play.is_empty() {
+        let args = script::parse_args(&opt.play)?;
+        let play_cmd = parse_play(&args).map_err(|e| anyhow!("--play: {}", e))?;
+        let devices = devices.as_ref().unwrap();
+        _output_stream = Some(start_play(&devices.output, &devices.config, &play_cmd, output_clock.clone())?);
+    }
+
+    // --- recording, without anything else reading the input
+    if let Some(record_cmd) = &record_cmd && input_cmd.is_none() {
+        let mut input = Input::start(&CmdInput::new(), devices.as_ref(), false, Some(&record_cmd.channels))?;
+        recording = Some(Recording::start(record_cmd, input.tap.take().unwrap(), input.sample_rate)?);
+        input_stream = Some(input.stream);
+    }
+
+    // --- input module
+    if let Some(input_cmd) = input_cmd {
+        //println!("input");
+        let mut input = Input::start(&input_cmd, devices.as_ref(), true, record_cmd.as_ref().map(|c| &c.channels[..]))?;
+        input.report_source(&report);
+        if let Some(record_cmd) = &record_cmd {
+            recording = Some(Recording::start(record_cmd, input.tap.take().unwrap(), input.sample_rate)?);
+        }
+        let Input { stream, mut consumer, channels, sample_rate, clock: input_clock, .. } = input;
+        input_stream = Some(stream);
+
+        let input_ch_ct = channels.len();
+        // the continuous measurements below only keep a summary, and only if asked to
+        let reporting = !opt.report.is_empty();
+        // applied to the rms only; the fit takes a while, so filters are copied from the one
+        let weight = Weighting::from_name(&opt.weight).map_err(|e| anyhow!("--weight: {}", e))?;
+        let weighting = weight.filter(sample_rate);
+        let unit = weight.db_unit();
+
+        if opt.mon {
+            //println!("mon");
+            let pb = ProgressBar::new(100);
+            pb.set_style(ProgressStyle::with_template("{bar} {msg}").unwrap());
+
+            let mut weighting = vec![weighting; input_ch_ct];
+            let mut meter = LoudnessMeter::new(input_ch_ct, sample_rate);
+            thread::spawn(move || {
+                let buf_sz = 4096;
+                loop {
+                    let mut sums = vec![0.0; input_ch_ct];
+                    let mut peaks = vec![0.0f32; input_ch_ct];
+                    let mut n = 0;
+                    while n < buf_sz {
+                        if consumer.occupied_len() >= input_ch_ct {
+                            let mut frame = [0.0; 64];
+                            let frame = &mut frame[..input_ch_ct];
+                            consumer.pop_slice(frame);
+                            for (i, s) in frame.iter().enumerate() {
+                                peaks[i] = peaks[i].max(s.abs());
+                                let w = weighting[i].process(*s);
+                                sums[i] += w * w;
+                            }
+                            meter.push(frame);
+                            n += 1;
+                        }
+                    }
+                    let rms: Vec<f32> = sums.iter().map(|s| (s / buf_sz as f32).sqrt()).collect();
+                    // the bar shows the first channel
+                    let (rms0, peak) = (rms[0], peaks[0]);
+                    let loudness = [
+                        ("momentary", meter.momentary(), "LUFS"),
+                        ("short-term", meter.short_term(), "LUFS"),
+                        ("integrated", meter.integrated(), "LUFS"),
+                        ("lra", meter.range(), "LU"),
+                        ("true-peak", Some(spectrum::to_db(meter.true_peak())), "dBTP"),
+                    ];
+                    if reporting {
+                        let mut report = report.lock().unwrap();
+                        for (i, ch) in channels.iter().enumerate() {
+                            report.update(*ch, "rms", spectrum::to_db(rms[i]), unit);
+                            report.update(*ch, "peak", spectrum::to_db(peaks[i]), "dBFS");
+                        }
+                        // loudness is of all the channels together, filed under the first
+                        for (metric, value, unit) in loudness {
+                            if let Some(value) = value {
+                                report.update(channels[0], metric, value, unit);
+                            }
+                        }
+                    }
+                    // loudness isn't defined until a block has been filled
+                    let show = |x: Option<f32>| x.map_or(String::from("-"), |x| format!("{x:.1}"));
+                    pb.set_message(format!("rms {:.1} {unit}  peak {:.1} dBFS  M {} S {} I {} LUFS  LRA {} LU  TP {} dBTP",
+                        spectrum::to_db(rms0), spectrum::to_db(peak),
+                        show(loudness[0].1), show(loudness[1].1), show(loudness[2].1), show(loudness[3].1), show(loudness[4].1)));
+                    pb.set_position((rms0 * 100.0) as u64);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            });
+        }
+
+        else if !opt.scope.is_empty() {
+            let args = script::parse_args(&opt.scope)?;
+            let scope_cmd = parse_scope(&args).map_err(|e| anyhow!("--scope: {}", e))?;
+            let positions = input_positions(&scope_cmd.channels, &channels).map_err(|e| anyhow!("--scope: {}", e))?;
+            let channel_ct = scope_cmd.channels.len();
+            let scopectl = Arc::new(Scope::new());
+            *scopectl.trigger.lock().unwrap() = scope_cmd.trigger;
+            *scopectl.timebase.lock().unwrap() = scope_cmd.timebase;
+            *scopectl.views.lock().unwrap() = vec![scope_cmd.view; channel_ct];
+            *scopectl.xy.lock().unwrap() = scope_cmd.xy;
+            {
+                let mut scope = scopectl.data.lock().unwrap();
+                for ch in &scope_cmd.channels {
+                    scope.push(ScopeChannel::new(&format!("ch{}", *ch)));
+                }
+            }
+            let scopectl_p = scopectl.clone();
+            let mut weighting = vec![weighting; channel_ct];
+            let mut spectrograms: Vec<Option<Spectrogram>> = scope_cmd.channels.iter()
+                .map(|_| scope_cmd.spectrogram.map(|cfg| Spectrogram::new(cfg, sample_rate, scope_cmd.window)))
+                .collect();
+            thread::spawn(move || {
+                // frames captured before this buffer, and the frame of the last trigger
+                let mut clock = 0;
+                let mut last_trigger: Option<usize> = None;
+                loop {
+                    let display_len = ((*scopectl_p.timebase.lock().unwrap() * DIVISIONS_X as f32 * sample_rate).round() as usize).max(2);
+                    // room to find a trigger ahead of a whole trace
+                    let buf_sz = (2 * display_len).max(4096);
+                    let buf = capture(&mut consumer, input_ch_ct, &positions, buf_sz);
+
+                    let trigger = *scopectl_p.trigger.lock().unwrap();
+                    let source = deinterleave(&buf, trigger.source.min(channel_ct - 1), channel_ct);
+                    let from = last_trigger.map_or(0, |t| (t + (trigger.holdoff * sample_rate) as usize).saturating_sub(clock));
+                    // leave a whole trace after the trigger
+                    let found = trigger.find(&source, from, buf_sz - display_len);
+                    if let Some(i) = found {
+                        last_trigger = Some(clock + i);
+                    }
+                    clock += buf_sz;
+                    scopectl_p.triggered.store(found.is_some(), Ordering::Relaxed);
+                    let show = match trigger.mode {
+                        TriggerMode::Auto => true,
+                        TriggerMode::Normal => found.is_some(),
+                        TriggerMode::Single => found.is_some() && scopectl_p.armed.swap(false, Ordering::Relaxed),
+                    };
+                    let trigger_index = found.unwrap_or(0);
+                    let shown = trigger_index..trigger_index + display_len;
+
+                    // let mut rms: f32 = 0.0;
+                    // let mut peak: f32 = 0.0;
+                    // let mut i: u32 = 0;
+                    // let mut display_samples: Vec<(f32, f32)> = Vec::new();
+                    // for s in buf {
+                    //     rms += (s * s);
+                    //     let sm = s.abs();
+                    //     if sm > peak { peak = sm; }
+                    //     if i < 512 {
+                    //         let point: (f32, f32) = (((i as f32) / sample_rate), s);
+                    //         display_samples.push(point);
+                    //         // if i < 24 {
+                    //         //     println!("sample {} {} {}", i, point.0, point.1);
+                    //         // }
+                    //     }
+                    //     i = i + 1;
+                    // }
+                    // let rms = (rms / (buf_sz as f32)).sqrt();
+
+                    for (i, ch) in scope_cmd.channels.iter().enumerate() {
+                        let mut d = calc_scope_channel(&buf, i, channel_ct, sample_rate, shown.clone(), scope_cmd.window, &mut weighting[i]);
+                        d.name = format!("ch{}", *ch);
+                        if let Some(spectrogram) = &mut spectrograms[i] {
+                            spectrogram.push(&deinterleave(&buf, i, channel_ct));
+                            d.spectrogram = Some(spectrogram.clone());
+                        }
+                        if reporting {
+                            let mut report = report.lock().unwrap();
+                            report.update(*ch, "rms", spectrum::to_db(d.rms), unit);
+                            report.update(*ch, "peak", spectrum::to_db(d.peak), "dBFS");
+                        }
+                        if show {
+                            scopectl_p.data.lock().unwrap()[i] = d;
+                        }
+                        // data.samples = display_samples;
+                        // data.peak = peak;
+                        // data.rms = rms;
+                    }
+                    let xy = *scopectl_p.xy.lock().unwrap();
+                    if show && xy.mode != XyMode::Off && xy.x.max(xy.y) < channel_ct {
+                        let mut trace = scopectl_p.xy_trace.lock().unwrap();
+                        trace.labels = (format!("ch{}", scope_cmd.channels[xy.x]), format!("ch{}", scope_cmd.channels[xy.y]));
+                        let (x, y) = (deinterleave(&buf, xy.x, channel_ct), deinterleave(&buf, xy.y, channel_ct));
+                        trace.push(&xy, &x[shown.clone()], &y[shown.clone()]);
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            });
+            run_scope(scopectl.clone()); // returns when the window is closed
+            return Ok(());
+        }
+
+        else if !opt.thd.is_empty() {
+            let args = script::parse_args(&opt.thd)?;
+            let mut thd_cmd = parse_thd(&args).map_err(|e| anyhow!("--thd: {}", e))?;
+            // measure every captured channel unless told otherwise
+            if thd_cmd.channels.is_empty() {
+                thd_cmd.channels = channels.clone();
+            }
+            // if we are generating the tone, we know where the fundamental is
+            if thd_cmd.freq.is_none() {
+                thd_cmd.freq = sinout_params.as_ref().map(|p| p.freq);
+            }
+            let positions = input_positions(&thd_cmd.channels, &channels).map_err(|e| anyhow!("--thd: {}", e))?;
+            let channel_ct = thd_cmd.channels.len();
+            thread::spawn(move || {
+                loop {
+                    let buf_sz = 16384;
+                    let buf = capture(&mut consumer, input_ch_ct, &positions, buf_sz);
+                    for (i, ch) in thd_cmd.channels.iter().enumerate() {
+                        let samples = deinterleave(&buf, i, channel_ct);
+                        let Some(d) = analysis::distortion(&samples, sample_rate, thd_cmd.freq, thd_cmd.harmonics) else {
+                            println!("ch{ch}: no signal");
+                            continue;
+                        };
+                        println!("ch{} f0 {:.1} Hz ({:.2} dBFS)  THD {:.2} dB ({:.4}%)  THD+N {:.2} dB ({:.4}%)  SINAD {:.2} dB",
+                            ch, d.fundamental_hz, d.fundamental_db,
+                            d.thd_db(), d.thd * 100.0,
+                            d.thd_n_db(), d.thd_n * 100.0,
+                            d.sinad_db);
+                        if reporting {
+                            let mut report = report.lock().unwrap();
+                            report.update(*ch, "freq", d.fundamental_hz, "Hz");
+                            report.update(*ch, "thd", d.thd_db(), "dB");
+                            report.update(*ch, "thd+n", d.thd_n_db(), "dB");
+                            report.update(*ch, "sinad", d.sinad_db, "dB");
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+            });
+        }
+
+        else if !opt.align.is_empty() {
+            let args = script::parse_args(&opt.align)?;
+            let mut align_cmd = parse_align(&args).map_err(|e| anyhow!("--align: {}", e))?;
+            let reference = align_cmd.reference;
+            if align_cmd.channels.is_empty() {
+                align_cmd.channels = channels.iter().copied().filter(|ch| *ch != reference).collect();
+            }
+            if align_cmd.channels.is_empty() {
+                return Err(anyhow!("--align needs two or more channels"));
+            }
+            // the reference comes last
+            let mut capture_channels = align_cmd.channels.clone();
+            capture_channels.push(reference);
+            let positions = input_positions(&capture_channels, &channels).map_err(|e| anyhow!("--align: {}", e))?;
+            let channel_ct = capture_channels.len();
+            let max_lag = (align_cmd.max_lag * sample_rate) as usize;
+            thread::spawn(move || {
+                loop {
+                    let buf = capture(&mut consumer, input_ch_ct, &positions, sample_rate as usize);
+                    let ref_samples = deinterleave(&buf, channel_ct - 1, channel_ct);
+                    for (i, ch) in align_cmd.channels.iter().enumerate() {
+                        let samples = deinterleave(&buf, i, channel_ct);
+                        let a = correlation::align(&ref_samples, &samples, sample_rate, max_lag);
+                        let delay_ms = a.delay / sample_rate * 1000.0;
+                        println!("ch{} vs ch{}: delay {:.2} samples ({:.3} ms)  polarity {} (r {:.3})  phase {:.1} deg at {:.1} Hz",
+                            ch, reference, a.delay, delay_ms,
+                            if a.inverted() { "INVERTED" } else { "normal" }, a.correlation,
+                            a.phase_deg, a.freq);
+                        if reporting {
+                            let mut report = report.lock().unwrap();
+                            report.update(*ch, "delay", delay_ms, "ms");
+                            report.update(*ch, "correlation", a.correlation, "");
+                            report.update(*ch, "phase", a.phase_deg, "deg");
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+            });
+        }
+
+        else if !opt.imd.is_empty() {
+            let args = script::parse_args(&opt.imd)?;
+            let mut imd_cmd = parse_imd(&args).map_err(|e| anyhow!("--imd: {}", e))?;
+            let Some(test) = imd_cmd.test.or(sinout_params.as_ref().and_then(|p| p.imd)) else {
+                return Err(anyhow!("--imd needs a :test, or a --sinout playing one"));
+            };
+            if imd_cmd.channels.is_empty() {
+                imd_cmd.channels = channels.clone();
+            }
+            let positions = input_positions(&imd_cmd.channels, &channels).map_err(|e| anyhow!("--imd: {}", e))?;
+            let channel_ct = imd_cmd.channels.len();
+            let (f1, f2, ratio) = test.tones();
+            println!("{} IMD: {f1} Hz and {f2} Hz, {ratio}:1", test.name());
+            thread::spawn(move || {
+                loop {
+                    // fine enough resolution to keep the 60 Hz sidebands of SMPTE apart
+                    let buf_sz = 32768;
+                    let buf = capture(&mut consumer, input_ch_ct, &positions, buf_sz);
+                    for (i, ch) in imd_cmd.channels.iter().enumerate() {
+                        let samples = deinterleave(&buf, i, channel_ct);
+                        let m = analysis::intermodulation(&samples, sample_rate, test);
+                        println!("ch{} IMD {:.2} dB ({:.4}%)  d2 {:.2} dB  d3 {:.2} dB",
+                            ch, m.imd_db(), m.imd() * 100.0, m.d2_db(), m.d3_db());
+                        if reporting {
+                            let mut report = report.lock().unwrap();
+                            report.update(*ch, "imd", m.imd_db(), "dB");
+                            report.update(*ch, "imd2", m.d2_db(), "dB");
+                            report.update(*ch, "imd3", m.d3_db(), "dB");
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+            });
+        }
+
+        else if !opt.ir.is_empty() {
+            let args = script::parse_args(&opt.ir)?;
+            let mut ir_cmd = parse_ir(&args).map_err(|e| anyhow!("--ir: {}", e))?;
+            let Some((start, end)) = sinout_params.as_ref().and_then(|p| p.sweep) else {
+                return Err(anyhow!("--ir needs a --sinout sweep"));
+            };
+            let sweep = Sweep::new(start, end, sinout_params.as_ref().unwrap().dur, sample_rate);
+            if ir_cmd.channels.is_empty() {
+                ir_cmd.channels = channels.clone();
+            }
+            let positions = input_positions(&ir_cmd.channels, &channels).map_err(|e| anyhow!("--ir: {}", e))?;
+            let channel_ct = ir_cmd.channels.len();
+            let frames = ((SWEEP_PREROLL + sweep.dur + ir_cmd.tail) * sample_rate) as usize;
+            println!("sweeping {start} Hz to {end} Hz in {} s", sweep.dur);
+            let buf = capture(&mut consumer, input_ch_ct, &positions, frames);
+            let ir_len = (ir_cmd.len * sample_rate) as usize;
+
+            for (i, ch) in ir_cmd.channels.iter().enumerate() {
+                let recording = deinterleave(&buf, i, channel_ct);
+                let result = sweep::analyze(&sweep, &recording, ir_cmd.harmonics, ir_len);
+                let prefix = format!("{}_ch{}", ir_cmd.out, ch);
+
+                write_wav(&format!("{prefix}_ir.wav"), &result.ir, sample_rate)?;
+                for (k, h) in result.harmonics.iter().enumerate() {
+                    write_wav(&format!("{prefix}_h{}.wav", k + 2), h, sample_rate)?;
+                }
+
+                let mut fr = String::from("freq_hz,mag_db,phase_deg\n");
+                for (f, mag, phase) in sweep::frequency_response(&result.ir, result.pre, sample_rate) {
+                    if f >= start && f <= end {
+                        fr += &format!("{f},{mag},{phase}\n");
+                    }
+                }
+                std::fs::write(format!("{prefix}_fr.csv"), fr)?;
+
+                // the preroll is part of the stimulus, so it is not latency
+                let delay = result.delay as f32 / sample_rate - SWEEP_PREROLL;
+                report.lock().unwrap().push(Record::new(*ch, "delay", delay * 1000.0, "ms"));
+                println!("ch{ch}: delay {:.2} ms, wrote {prefix}_ir.wav, {prefix}_fr.csv and {} harmonic irs",
+                    delay * 1000.0, result.harmonics.len());
+            }
+            return Ok(());
+        }
+
+        else if let Some(xt_cmd) = crosstalk_cmd {
+            let (steps, step) = sinout_params.as_ref().unwrap().steps.clone().unwrap();
+            let positions = input_positions(&xt_cmd.channels, &channels).map_err(|e| anyhow!("--crosstalk: {}", e))?;
+            let channel_ct = xt_cmd.channels.len();
+            let step_frames = (step * sample_rate) as u64;
+            let frames = steps.len() as u64 * step_frames + sample_rate as u64;
+            println!("driving {} outputs in turn at {} frequencies, {} s each", xt_cmd.drive.len(), steps.len() / xt_cmd.drive.len(), step);
+            let buf = capture(&mut consumer, input_ch_ct, &positions, frames as usize);
+            let (Some(out_clock), Some(in_clock)) = (*output_clock.lock().unwrap(), *input_clock.lock().unwrap()) else {
+                return Err(anyhow!("no timing from the audio streams"));
+            };
+            let recordings: Vec<Vec<f32>> = (0..channel_ct).map(|i| deinterleave(&buf, i, channel_ct)).collect();
+
+            let mut csv = String::from("freq_hz,out_ch,in_ch,db\n");
+            let mut report = report.lock().unwrap();
+            // steps go through every output at one frequency before moving to the next
+            for (f, freq_steps) in steps.chunks(xt_cmd.drive.len()).enumerate() {
+                let freq = freq_steps[0].freq;
+                println!("{freq} Hz, dB relative to the driven output's own input:");
+                println!("{:>8}{}", "", xt_cmd.channels.iter().map(|ch| format!("{:>9}", format!("in{ch}"))).collect::<String>());
+                for (k, out) in xt_cmd.drive.iter().enumerate() {
+                    let Some(window) = step_window(f * xt_cmd.drive.len() + k, step_frames, xt_cmd.settle, (&out_clock, &in_clock), sample_rate, recordings[0].len()) else {
+                        println!("{:>8} not captured", format!("out{out}"));
+                        continue;
+                    };
+                    let levels: Vec<f32> = recordings.iter().map(|r| analysis::lock_in(&r[window.clone()], sample_rate, freq).0).collect();
+                    // nothing to compare the others with if the driven output doesn't come back
+                    if spectrum::to_db(levels[k]) < CROSSTALK_FLOOR_DB {
+                        println!("{:>8} no signal on in{}", format!("out{out}"), xt_cmd.channels[k]);
+                        continue;
+                    }
+                    let mut row = format!("{:>8}", format!("out{out}"));
+                    for (j, ch) in xt_cmd.channels.iter().enumerate() {
+                        let db = spectrum::to_db(levels[j] / levels[k]);
+                        csv += &format!("{freq},{out},{ch},{db}\n");
+                        if j == k {
+                            row += &format!("{:>9}", "-");
+                        } else {
+                            row += &format!("{db:>9.1}");
+                            report.push(Record::new(*ch, &format!("crosstalk out{out} {freq:.0}Hz"), db, "dB"));
+                        }
+                    }
+                    println!("{row}");
+                }
+            }
+            std::fs::write(format!("{}.csv", xt_cmd.out), csv)?;
+            println!("wrote {}.csv", xt_cmd.out);
+            return Ok(());
+        }
+
+        else if let Some(mut fr_cmd) = freqresp_cmd {
+            let (steps, step) = sinout_params.as_ref().unwrap().steps.clone().unwrap();
+            let freqs: Vec<f32> = steps.iter().map(|s| s.freq).collect();
+            if fr_cmd.channels.is_empty() {
+                fr_cmd.channels = channels.iter().copied().filter(|ch| Some(*ch) != fr_cmd.reference).collect();
+            }
+            if fr_cmd.channels.is_empty() {
+                return Err(anyhow!("--freqresp: no channels to measure besides the reference"));
+            }
+            // capture the reference along with the channels measured against it
+            let mut capture_channels = fr_cmd.channels.clone();
+            capture_channels.extend(fr_cmd.reference);
+            let positions = input_positions(&capture_channels, &channels).map_err(|e| anyhow!("--freqresp: {}", e))?;
+            let channel_ct = capture_channels.len();
+            let step_frames = (step * sample_rate) as u64;
+            let frames = freqs.len() as u64 * step_frames + sample_rate as u64;
+            println!("stepping through {} frequencies, {} s each", freqs.len(), step);
+            let buf = capture(&mut consumer, input_ch_ct, &positions, frames as usize);
+            let (Some(out_clock), Some(in_clock)) = (*output_clock.lock().unwrap(), *input_clock.lock().unwrap()) else {
+                return Err(anyhow!("no timing from the audio streams"));
+            };
+            let recordings: Vec<Vec<f32>> = (0..channel_ct).map(|i| deinterleave(&buf, i, channel_ct)).collect();
+            let ampl = sinout_params.as_ref().unwrap().ampl;
+
+            // per channel, (freq, gain dB, phase degrees) at each step
+            let mut response: Vec<Vec<(f32, f32, Option<f32>)>> = vec![Vec::new(); fr_cmd.channels.len()];
+            for (k, freq) in freqs.iter().enumerate() {
+                let Some(window) = step_window(k, step_frames, fr_cmd.settle, (&out_clock, &in_clock), sample_rate, recordings[0].len()) else {
+                    println!("{freq} Hz: not captured");
+                    continue;
+                };
+                let detect = |i: usize| analysis::lock_in(&recordings[i][window.clone()], sample_rate, *freq);
+                let reference = fr_cmd.reference.map(|_| detect(channel_ct - 1));
+                for (i, r) in response.iter_mut().enumerate() {
+                    let (a, phase) = detect(i);
+                    r.push(match reference {
+                        Some((ref_a, ref_phase)) => {
+                            let deg = (phase - ref_phase).to_degrees();
+                            (*freq, spectrum::to_db(a / ref_a), Some(deg - 360.0 * (deg / 360.0).round()))
+                        },
+                        None => (*freq, spectrum::to_db(a / ampl), None),
+                    });
+                }
+            }
+
+            let mut csv = String::from("freq_hz");
+            for ch in &fr_cmd.channels {
+                csv += &format!(",ch{ch}_db,ch{ch}_deg");
+            }
+            csv.push('\n');
+            for k in 0..response[0].len() {
+                csv += &response[0][k].0.to_string();
+                for r in &response {
+                    csv += &format!(",{},{}", r[k].1, r[k].2.map(|p| p.to_string()).unwrap_or_default());
+                }
+                csv.push('\n');
+            }
+            std::fs::write(format!("{}.csv", fr_cmd.out), csv)?;
+            write_bode(&format!("{}.svg", fr_cmd.out), &fr_cmd.channels, &response)?;
+
+            let relative_to = fr_cmd.reference.map_or(String::from("the stimulus"), |ch| format!("ch{ch}"));
+            println!("response relative to {relative_to}:");
+            let mut report = report.lock().unwrap();
+            for (ch, r) in fr_cmd.channels.iter().zip(&response) {
+                for (freq, db, phase) in r {
+                    match phase {
+                        Some(phase) => println!("  ch{ch} {freq:8.1} Hz  {db:7.2} dB  {phase:7.1} deg"),
+                        None => println!("  ch{ch} {freq:8.1} Hz  {db:7.2} dB"),
+                    }
+                    report.push(Record::new(*ch, &format!("gain {freq:.0}Hz"), *db, "dB"));
+                    if let Some(phase) = phase {
+                        report.push(Record::new(*ch, &format!("phase {freq:.0}Hz"), *phase, "deg"));
+                    }
+                }
+            }
+            println!("wrote {}.csv and {}.svg", fr_cmd.out, fr_cmd.out);
+            return Ok(());
+        }
+
+        else if !opt.multitone.is_empty() {
+            let args = script::parse_args(&opt.multitone)?;
+            let mut mt_cmd = parse_multitone(&args).map_err(|e| anyhow!("--multitone: {}", e))?;
+            let Some(params) = sinout_params.as_ref().filter(|p| p.tones.is_some()) else {
+                return Err(anyhow!("--multitone needs a --sinout multitone"));
+            };
+            let mt = params.multitone(sample_rate)?.unwrap();
+            if mt_cmd.channels.is_empty() {
+                mt_cmd.channels = channels.clone();
+            }
+            let positions = input_positions(&mt_cmd.channels, &channels).map_err(|e| anyhow!("--multitone: {}", e))?;
+            let channel_ct = mt_cmd.channels.len();
+            // the first period lets the system settle, and covers the latency
+            let buf = capture(&mut consumer, input_ch_ct, &positions, (mt_cmd.periods + 1) * mt.n);
+
+            for (i, ch) in mt_cmd.channels.iter().enumerate() {
+                let recording = deinterleave(&buf, i, channel_ct);
+                let result = multitone::analyze(&mt, params.ampl, &recording[mt.n..])?;
+                let path = format!("{}_ch{}.csv", mt_cmd.out, ch);
+                let mut csv = String::from("freq_hz,tone,level_db,noise_db\n");
+                for b in &result.bins {
+                    csv += &format!("{},{},{},{}\n", b.freq, b.tone as u8, b.level_db, b.noise_db);
+                }
+                std::fs::write(&path, csv)?;
+
+                println!("ch{ch}:");
+                for t in &result.tones {
+                    println!("  {:8.1} Hz  {:7.2} dBFS  gain {:6.2} dB  phase {:7.1} deg", t.freq, t.level_db, t.gain_db, t.phase_deg);
+                }
+                println!("  distortion {:.2} dB  noise {:.2} dB, wrote {path}", result.distortion_db, result.noise_db);
+                let mut report = report.lock().unwrap();
+                for t in &result.tones {
+                    report.push(Record::new(*ch, &format!("gain {:.0}Hz", t.freq), t.gain_db, "dB"));
+                }
+                report.push(Record::new(*ch, "distortion", result.distortion_db, "dB"));
+                report.push(Record::new(*ch, "noise", result.noise_db, "dB"));
+            }
+            return Ok(());
+        }
+
+        else if !opt.latency.is_empty() {
+            let args = script::parse_args(&opt.latency)?;
+            let mut latency_cmd = parse_latency(&args).map_err(|e| anyhow!("--latency: {}", e))?;
+            let Some((burst, period)) = sinout_params.as_ref().and_then(|p| p.burst.map(|b| (b, p.period))) else {
+                return Err(anyhow!("--latency needs a --sinout burst"));
+            };
+            // each burst is looked for within its own period, so longer latencies would alias
+            if period <= latency_cmd.max {
+                return Err(anyhow!("--latency: a burst period of {} s can't measure latencies up to :max {} s", period, latency_cmd.max));
+            }
+            if latency_cmd.channels.is_empty() {
+                latency_cmd.channels = channels.clone();
+            }
+            let positions = input_positions(&latency_cmd.channels, &channels).map_err(|e| anyhow!("--latency: {}", e))?;
+            let channel_ct = latency_cmd.channels.len();
+            let template = burst.samples()?;
+            let period_frames = (period * sample_rate) as u64;
+
+            // capture starts at input frame 0, as long as the ring never overruns
+            let frames = (latency_cmd.reps as u64 + 2) * period_frames;
+            let buf = capture(&mut consumer, input_ch_ct, &positions, frames as usize);
+            let (Some(out_clock), Some(in_clock)) = (*output_clock.lock().unwrap(), *input_clock.lock().unwrap()) else {
+                return Err(anyhow!("no timing from the audio streams"));
+            };
+
+            let to_input_frame = |frame: u64| input_frame_at(frame, &out_clock, &in_clock, sample_rate);
+
+            for (i, ch) in latency_cmd.channels.iter().enumerate() {
+                let recording = deinterleave(&buf, i, channel_ct);
+                let r = correlation::cross_correlate(&recording, &template);
+                let mut latencies: Vec<f64> = Vec::new();
+                for k in 0.. {
+                    if latencies.len() >= latency_cmd.reps {
+                        break;
+                    }
+                    let start = to_input_frame(k * period_frames);
+                    let end = start + period_frames as f64;
+                    if end > recording.len() as f64 {
+                        break;
+                    }
+                    if start < 0.0 {
+                        continue;
+                    }
+                    // search the period following the burst's emission
+                    let (pos, _) = correlation::peak(&r[start as usize..end as usize]);
+                    latencies.push(pos as f64 - start.fract());
+                }
+                if latencies.is_empty() {
+                    println!("ch{ch}: no bursts captured");
+                    continue;
+                }
+                let min = latencies.iter().copied().fold(f64::MAX, f64::min);
+                let max = latencies.iter().copied().fold(f64::MIN, f64::max);
+                let mean = latencies.iter().sum::<f64>() / latencies.len() as f64;
+                let ms = |frames: f64| frames * 1000.0 / sample_rate as f64;
+                {
+                    let mut report = report.lock().unwrap();
+                    report.push(Record::new(*ch, "latency min", ms(min) as f32, "ms"));
+                    report.push(Record::new(*ch, "latency mean", ms(mean) as f32, "ms"));
+                    report.push(Record::new(*ch, "latency max", ms(max) as f32, "ms"));
+                    report.push(Record::new(*ch, "jitter", ms(max - min) as f32, "ms"));
+                }
+                println!("ch{ch}: latency min {:.2} mean {:.2} max {:.2} samples ({:.3} / {:.3} / {:.3} ms), jitter {:.2} samples p-p over {} bursts",
+                    min, mean, max, ms(min), ms(mean), ms(max), max - min, latencies.len());
+            }
+            return Ok(());
+        }
+    }
+
+    // stop on ctrl-c like at the end of the dur, so recordings are finalised and the report written
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupted_h = interrupted.clone();
+    ctrlc::set_handler(move || interrupted_h.store(true, Ordering::Relaxed))?;
+    let start = std::time::Instant::now();
+    let done = || if opt.dur == 0.0 {
+        // a file ends by itself, as does a recording with a dur; a device runs until interrupted
+        input_stream.as_ref().is_some_and(|s| s.finished()) || recording.as_ref().is_some_and(|r| r.finished())
+    } else {
+        start.elapsed().as_secs_f32() >= opt.dur
+    };
+    while !done() && !interrupted.load(Ordering::Relaxed) {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+fn start_sinout(device: &cpal::Device, config: &cpal::SupportedStreamConfig, mut params: CmdSinout, clock: SharedClock) -> Result<cpal::Stream> {
+    if let Some(t) = params.per_channel.iter().find(|t| t.ch as u16 >= config.channels()) {
+        return Err(anyhow!("ch {} is not an output; the device has {}", t.ch, config.channels()));
+    }
+    // the parser can't know the sample rate to check these
+    if let Some(burst) = params.burst {
+        // a cut short MLS loses its flat spectrum and single correlation peak
+        let len = burst.samples()?.len();
+        if ((params.period * config.sample_rate().0 as f32) as usize) < len {
+            return Err(anyhow!("burst period {} s is shorter than the {} sample burst", params.period, len));
+        }
+    }
+    if let Some((_, end)) = params.sweep && end > config.sample_rate().0 as f32 / 2.0 {
+        return Err(anyhow!("sweep end {} Hz is above half the sample rate", end));
+    }
+    // if user passes no channel numbers, send the signal to all the channels
+    if params.channels.is_empty() {
+        params.channels.resize(config.channels() as usize, 1.0);
+    }
+
+    match config.sample_format() {
+        //cpal::SampleFormat::I8 => run::<i8>(&device, &config.into()),
+        //cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()),
+        //cpal::SampleFormat::I24 => run::<I24>(&device, &config.into()),
+        //cpal::SampleFormat::I32 => run::<i32>(&device, &config.into()),
+        // cpal::SampleFormat::I48 => run::<I48>(&device, &config.into()),
+        //cpal::SampleFormat::I64 => run::<i64>(&device, &config.into()),
+        //cpal::SampleFormat::U8 => run::<u8>(&device, &config.into()),
+        //cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()),
+        // cpal::SampleFormat::U24 => run::<U24>(&device, &config.into()),
+        //cpal::SampleFormat::U32 => run::<u32>(&device, &config.into()),
+        // cpal::SampleFormat::U48 => run::<U48>(&device, &config.into()),
+        //cpal::SampleFormat::U64 => run::<u64>(&device, &config.into()),
+        cpal::SampleFormat::F32 => run_sinout::<f32>(device, &config.clone().into(), params, clock),
+        //cpal::SampleFormat::F64 => run::<f64>(&device, &config.into()),
+        sample_format => Err(anyhow!("Unsupported sample format '{sample_format}'")),
+    }
+}
+
+// Start capturing `channels` from the input device. Frames holding just those
+// channels are pushed to the returned ring buffer, and `tap` channels to its ring.
+fn start_input(device: &cpal::Device, config: &cpal::SupportedStreamConfig, channels: &[u8], warn_overrun: bool, mut tap: Option<TapProducer>) -> Result<(cpal::Stream, HeapCons<f32>, SharedClock)> {
+    let config: cpal::StreamConfig = config.clone().into();
+    let channel_ct = config.channels as usize;
+    let tap_channels = tap.as_ref().map(|t| &t.channels[..]).unwrap_or(&[]);
+    if let Some(ch) = channels.iter().chain(tap_channels).find(|ch| **ch as usize >= channel_ct) {
+        return Err(anyhow!("input has no ch {}", ch));
+    }
+    let ring = HeapRb::<f32>::new(48000 * channel_ct);
+    let (mut producer, consumer) = ring.split();
+
+    let channels = channels.to_vec();
+    let clock: SharedClock = Arc::new(Mutex::new(None));
+    let clock_p = clock.clone();
+    let mut frames_read: u64 = 0;
+
+    let input_data_fn = move |data: &[f32], info: &cpal::InputCallbackInfo| {
+        // these frames have all arrived by the time of the callback
+        frames_read += (data.len() / channel_ct) as u64;
+        *clock_p.lock().unwrap() = Some(StreamClock { frame: frames_read, at: info.timestamp().callback });
+        let mut overrun = false;
+        //println!("input buffer {} samples", data.len());
+        for frame in data.chunks_exact(channel_ct) {
+            for ch in &channels {
+                if producer.try_push(frame[*ch as usize]).is_err() {
+                    overrun = true;
+                }
+            }
+            if overrun && warn_overrun {
+                eprintln!("output stream fell behind: try increasing latency");
+            }
+            if let Some(tap) = &mut tap {
+                tap.push(frame);
+            }
+        }
+    };
+
+    //println!("building input stream");
+    let stream = device.build_input_stream(&config, input_data_fn, err_fn, None)?;
+    //println!("built input stream");
+    Ok((stream, consumer, clock))
+}
+
+// Play `channels` of a recording into a ring buffer, in real time or as fast
+// as the ring is emptied. Once the recording has been consumed the feed is
+// marked done and carries on with silence, so pending captures still finish.
+fn start_file_input(file: AudioFile, channels: &[u8], realtime: bool, mut tap: Option<TapProducer>) -> Result<(FileFeed, HeapCons<f32>)> {
+    let tap_channels = tap.as_ref().map(|t| &t.channels[..]).unwrap_or(&[]);
+    if let Some(ch) = channels.iter().chain(tap_channels).find(|ch| **ch as usize >= file.channels) {
+        return Err(anyhow!("file has no ch {}", ch));
+    }
+    let ring = HeapRb::<f32>::new(file.sample_rate as usize * channels.len());
+    let (mut producer, consumer) = ring.split();
+
+    let feed = FileFeed { stop: Arc::new(AtomicBool::new(false)), done: Arc::new(AtomicBool::new(false)) };
+    let stop = feed.stop.clone();
+    let done = feed.done.clone();
+    let channels = channels.to_vec();
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(1));
+
+    thread::spawn(move || {
+        let frames = file.frames();
+        let silence = vec![0.0; file.channels];
+        let start = std::time::Instant::now();
+        let mut i = 0;
+        while !stop.load(Ordering::Relaxed) {
+            if i == frames {
+                // wait for the ring to drain, unless nobody is reading it
+                let mut left = producer.occupied_len();
+                let mut since = std::time::Instant::now();
+                while left > 0 && since.elapsed().as_secs_f32() < 1.0 && !stop.load(Ordering::Relaxed) {
+                    pause();
+                    if producer.occupied_len() != left {
+                        left = producer.occupied_len();
+                        since = std::time::Instant::now();
+                    }
+                }
+                done.store(true, Ordering::Relaxed);
+            }
+            let frame = if i < frames { &file.samples[i * file.channels..(i + 1) * file.channels] } else { &silence[..] };
+
+            if realtime {
+                // keep pace a block at a time, dropping samples if the ring is full like a device would
+                if i % 1024 == 0 {
+                    let due = start + std::time::Duration::from_secs_f64(i as f64 / file.sample_rate as f64);
+                    std::thread::sleep(due.saturating_duration_since(std::time::Instant::now()));
+                }
+            } else {
+                while producer.vacant_len() < channels.len() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    pause();
+                }
+            }
+            for ch in &channels {
+                let _ = producer.try_push(frame[*ch as usize]);
+            }
+            // only the recording itself goes to the tap, not the silence after it
+            if let Some(tap) = &mut tap && i < frames {
+                tap.push(frame);
+            }
+            i += 1;
+        }
+    });
+    Ok((feed, consumer))
+}
+
+// where each of the device `channels` sits in a frame of the `captured` ones
+fn input_positions(channels: &[u8], captured: &[u8]) -> Result<Vec<u8>> {
+    channels.iter()
+        .map(|ch| captured.iter().position(|c| c == ch).map(|p| p as u8)
+            .ok_or_else(|| anyhow!("ch {} is not being captured", ch)))
+        .collect()
+}
+
+// Pull `buf_sz` frames from the input ring, keeping only `channels` (indexes into
+// the captured frame). Returns them interleaved.
+fn capture(consumer: &mut HeapCons<f32>, input_ch_ct: usize, channels: &[u8], buf_sz: usize) -> Vec<f32> {
+    let mut buf: Vec<f32> = Vec::with_capacity(buf_sz * channels.len());
+    let mut frame = vec![0.0; input_ch_ct];
+    while buf.len() < buf_sz * channels.len() {
+        if consumer.occupied_len() >= input_ch_ct {
+            consumer.pop_slice(&mut frame);
+            for ch in channels {
+                buf.push(frame[*ch as usize]);
+            }
+        }
+    }
+    buf
+}
+
+fn deinterleave(buf: &[f32], ch: usize, ch_ct: usize) -> Vec<f32> {
+    buf.iter().skip(ch).step_by(ch_ct).copied().collect()
+}
+
+fn write_wav(path: &str, samples: &[f32], sample_rate: f32) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for s in samples {
+        writer.write_sample(*s)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+// Magnitude and, when there is a reference, phase against log frequency.
+fn write_bode(path: &str, channels: &[u8], response: &[Vec<(f32, f32, Option<f32>)>]) -> Result<()> {
+    use plotters::prelude::*;
+    let points = || response.iter().flatten();
+    let (lo, hi) = points().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+    let (db_lo, db_hi) = points().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+    if lo >= hi {
+        return Err(anyhow!("need two or more frequencies to plot"));
+    }
+    let has_phase = points().any(|p| p.2.is_some());
+
+    let root = SVGBackend::new(path, (900, if has_phase { 700 } else { 400 })).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (upper, lower) = if has_phase { root.split_vertically(350) } else { (root.clone(), root.clone()) };
+
+    let mut mag = ChartBuilder::on(&upper)
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d((lo..hi).log_scale(), (db_lo - 3.0)..(db_hi + 3.0))?;
+    mag.configure_mesh().x_desc("Hz").y_desc("dB").draw()?;
+    for (i, (ch, r)) in channels.iter().zip(response).enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        mag.draw_series(LineSeries::new(r.iter().map(|p| (p.0, p.1)), color))?
+            .label(format!("ch{ch}"))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+    }
+    mag.configure_series_labels().background_style(WHITE.mix(0.8)).border_style(BLACK).draw()?;
+
+    if has_phase {
+        let mut phase = ChartBuilder::on(&lower)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d((lo..hi).log_scale(), -180f32..180f32)?;
+        phase.configure_mesh().x_desc("Hz").y_desc("degrees").draw()?;
+        for (i, r) in response.iter().enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            phase.draw_series(LineSeries::new(r.iter().filter_map(|p| p.2.map(|deg| (p.0, deg))), color))?;
+        }
+    }
+    root.present()?;
+    Ok(())
+}
+
+fn calc_scope_channel(buf: &[f32], ch: usize, ch_ct: usize, sample_rate: f32, shown: std::ops::Range<usize>, window: Window, weighting: &mut WeightingFilter) -> ScopeChannel {
+    let mut d = ScopeChannel::new("");
+    let capture = deinterleave(buf, ch, ch_ct);
+    for s in &capture {
+        let w = weighting.process(*s);
+        d.rms += w * w;
+        d.peak = d.peak.max(s.abs());
+        d.mean += s;
     }
-    .expect("failed to find output device");
-    println!("Output device: {}", output_device.name()?);
+    d.rms = (d.rms / (capture.len() as f32)).sqrt();
+    d.mean /= capture.len() as f32;
 
-    let input_device = if opt.device == "default" {
-        host.default_input_device()
+    let t = |i: usize| (i - shown.start) as f32 / sample_rate;
+    d.span = shown.len() as f32 / sample_rate;
+    let trace = &capture[shown.clone()];
+    if trace.len() <= SCOPE_MAX_POINTS {
+        d.samples = trace.iter().enumerate().map(|(i, s)| (t(shown.start + i), *s)).collect();
     } else {
-        host.input_devices()?
-            .find(|x| x.name().map(|y| y == opt.device).unwrap_or(false))
+        let stretch = trace.len().div_ceil(SCOPE_MAX_POINTS / 2);
+        for (k, chunk) in trace.chunks(stretch).enumerate() {
+            let i = shown.start + k * stretch;
+            let (lo, hi) = chunk.iter().fold((f32::MAX, f32::MIN), |(lo, hi), s| (lo.min(*s), hi.max(*s)));
+            d.samples.push((t(i), lo));
+            d.samples.push((t(i + chunk.len() - 1), hi));
+        }
     }
-    .expect("failed to find input device");
-    println!("Input device: {}", input_device.name()?);
-
-    let config = output_device.default_output_config().unwrap();
-    println!("Default output config: {config:?}");
-
-    let output_stream: Option<cpal::Stream>;
-    let input_stream: Option<cpal::Stream>;
+    d.fft = spectrum::spectrum(&capture[..SCOPE_FFT_LEN.min(capture.len())], sample_rate, window);
+    d
+}
 
-    // --- sinout
-    if opt.sinout.len() > 0 {
-        //println!("sinout");
+fn parse_input(args: &Value) -> Result<CmdInput> {
+    let mut cmd = CmdInput::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "file" => cmd.file = Some(as_name(val)?.to_string()),
+            "speed" => cmd.realtime = match as_name(val)? {
+                "realtime" => true,
+                "fast" => false,
+                s => return Err(anyhow!("unknown speed {}", s)),
+            },
+            "format" => cmd.raw = Some(PcmFormat::from_name(as_name(val)?)?),
+            "channels" => cmd.raw_channels = as_usize(val)?,
+            "rate" => cmd.raw_rate = as_usize(val)? as u32,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
 
-        let sinout_cmd = lexpr::from_str(&opt.sinout)?;
-
-        let mut params = parse_sinout(&sinout_cmd)?;
-
-        // if user passes no channel numbers, send the signal to all the channels
-        if params.channels.is_empty() {
-            params.channels.resize(config.channels() as usize, 1.0);
-        }
-
-        output_stream = match config.sample_format() {
-            //cpal::SampleFormat::I8 => run::<i8>(&device, &config.into()),
-            //cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()),
-            //cpal::SampleFormat::I24 => run::<I24>(&device, &config.into()),
-            //cpal::SampleFormat::I32 => run::<i32>(&device, &config.into()),
-            // cpal::SampleFormat::I48 => run::<I48>(&device, &config.into()),
-            //cpal::SampleFormat::I64 => run::<i64>(&device, &config.into()),
-            //cpal::SampleFormat::U8 => run::<u8>(&device, &config.into()),
-            //cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()),
-            // cpal::SampleFormat::U24 => run::<U24>(&device, &config.into()),
-            //cpal::SampleFormat::U32 => run::<u32>(&device, &config.into()),
-            // cpal::SampleFormat::U48 => run::<U48>(&device, &config.into()),
-            //cpal::SampleFormat::U64 => run::<u64>(&device, &config.into()),
-            cpal::SampleFormat::F32 => Some(run_sinout::<f32>(&output_device, &config.clone().into(), params).unwrap()),
-            //cpal::SampleFormat::F64 => run::<f64>(&device, &config.into()),
-            sample_format => panic!("Unsupported sample format '{sample_format}'"),
-        };
-    }
+    Ok(cmd)
+}
 
-    // --- input module
-    if opt.input.len() > 0 {
-        //println!("input");
-        let input_args = lexpr::from_str(&opt.input)?;
-        let input_cmd = parse_input(&input_args)?;
-
-        let config: cpal::StreamConfig = config.into();
-        let channel_ct = config.channels as usize;
-        let sample_rate = config.sample_rate.0 as f32;
-        let ring = HeapRb::<f32>::new(48000 * channel_ct);
-        let (mut producer, mut consumer) = ring.split();
-
-        let input_cmd_p = input_cmd.clone();
-
-        let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            let mut overrun = false;
-            //println!("input buffer {} samples", data.len());
-            for frame in data.chunks_exact(channel_ct) {
-                for ch in &input_cmd_p.channels {
-                    if producer.try_push(frame[*ch as usize]).is_err() {
-                        overrun = true;
-                    }
+fn parse_scope(args: &Value) -> Result<CmdScope> {
+    let mut cmd = CmdScope::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "window" => cmd.window = Window::from_name(as_name(val)?)?,
+            "spectrogram" => cmd.spectrogram = parse_spectrogram(val)?,
+            "timebase" => cmd.timebase = as_f32(val)?.clamp(MIN_TIMEBASE, MAX_TIMEBASE),
+            // a level per division gives a dBFS scale
+            "vdiv" => cmd.view.vertical = match val.as_str().is_some_and(|s| s.ends_with("dB")) {
+                true => Vertical::Db(as_db(val)?),
+                false => Vertical::Linear(as_gain(val)?),
+            },
+            "offset" => cmd.view.offset = as_f32(val)?,
+            "coupling" => cmd.view.coupling = Coupling::from_name(as_name(val)?)?,
+            "xy" => for_plist(val, |key, val| {
+                match key {
+                    "mode" => cmd.xy.mode = XyMode::from_name(as_name(val)?)?,
+                    "ch" => cmd.xy_ch = Some(as_channels(val)?),
+                    "persistence" => cmd.xy.persistence = as_usize(val)?,
+                    _ => return Err(anyhow!("unknown key")),
                 }
-                if overrun {
-                    eprintln!("output stream fell behind: try increasing latency");
+                Ok(())
+            })?,
+            "trigger" => for_plist(val, |key, val| {
+                match key {
+                    "level" => cmd.trigger.level = as_gain(val)?,
+                    "slope" => cmd.trigger.slope = Slope::from_name(as_name(val)?)?,
+                    "ch" => cmd.trigger_ch = Some(as_channels(val)?.first().copied().ok_or_else(|| anyhow!("expected a channel"))?),
+                    "holdoff" => cmd.trigger.holdoff = as_f32(val)?,
+                    "mode" => cmd.trigger.mode = TriggerMode::from_name(as_name(val)?)?,
+                    _ => return Err(anyhow!("unknown key")),
                 }
-            }
+                Ok(())
+            })?,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    // the trigger and xy channels below are looked up among these
+    if cmd.channels.is_empty() {
+        return Err(anyhow!("expected :ch, the channels to show"));
+    }
+    // the trigger source is given as a device channel but kept as an index into the ones shown
+    if let Some(ch) = cmd.trigger_ch {
+        cmd.trigger.source = cmd.channels.iter().position(|c| *c == ch)
+            .ok_or_else(|| anyhow!("trigger: channel {} isn't shown", ch))?;
+    }
+    if let Some(chs) = &cmd.xy_ch {
+        let [x, y] = chs[..] else {
+            return Err(anyhow!("xy: expected two channels"));
         };
+        let index = |ch: u8| cmd.channels.iter().position(|c| *c == ch).ok_or_else(|| anyhow!("xy: channel {} isn't shown", ch));
+        (cmd.xy.x, cmd.xy.y) = (index(x)?, index(y)?);
+    } else if cmd.xy.mode != XyMode::Off && cmd.channels.len() < 2 {
+        return Err(anyhow!("xy: needs two channels"));
+    }
+
+    Ok(cmd)
+}
 
-        //println!("building input stream");
-        input_stream = Some(input_device.build_input_stream(&config, input_data_fn, err_fn, None).unwrap());
-        //println!("built input stream");
+// #t for the defaults, or a plist of settings
+fn parse_spectrogram(args: &Value) -> Result<Option<SpectrogramConfig>> {
+    if let Some(on) = args.as_bool() {
+        return Ok(on.then(SpectrogramConfig::default));
+    }
+    let mut cfg = SpectrogramConfig::default();
+    for_plist(args, |key, val| {
+        match key {
+            "fft" => cfg.fft = as_usize(val)?,
+            "overlap" => cfg.overlap = as_gain(val)?,
+            "floor" => cfg.floor_db = as_db(val)?,
+            "ceiling" => cfg.ceiling_db = as_db(val)?,
+            "colormap" => cfg.colormap = Colormap::from_name(as_name(val)?)?,
+            "rows" => cfg.rows = as_usize(val)?,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    if cfg.fft < 16 {
+        return Err(anyhow!("fft must be at least 16"));
+    }
+    if !(0.0..1.0).contains(&cfg.overlap) {
+        return Err(anyhow!("overlap must be from 0 to under 100%"));
+    }
+    if cfg.floor_db >= cfg.ceiling_db {
+        return Err(anyhow!("floor must be below ceiling"));
+    }
+    if cfg.rows == 0 {
+        return Err(anyhow!("rows must be at least 1"));
+    }
+    Ok(Some(cfg))
+}
 
-        let input_ch_ct = input_cmd.channels.len();
+fn parse_thd(args: &Value) -> Result<CmdThd> {
+    let mut cmd = CmdThd::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "harmonics" => cmd.harmonics = as_usize(val)?,
+            "freq" => cmd.freq = Some(as_f32(val)?),
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
 
-        if opt.mon {
-            //println!("mon");
-            let pb = ProgressBar::new(100);
-            pb.set_style(ProgressStyle::with_template("{bar} {msg}").unwrap());
+    Ok(cmd)
+}
 
-            thread::spawn(move || {
-                loop {
-                    let mut buf: Vec<f32> = Vec::new();
-                    let buf_sz = 4096;
-                    loop {
-                        if buf.len() >= buf_sz {
-                            break;
-                        }
-                        if consumer.occupied_len() >= input_ch_ct {
-                            let mut frame = [0.0; 64];
-                            consumer.pop_slice(&mut frame);
-                            buf.push(frame[0]);
-                        }
-                    }
-                    let mut rms: f32 = 0.0;
-                    let mut peak: f32 = 0.0;
-                    for s in buf {
-                        rms += (s * s);
-                        let sm = s.abs();
-                        if sm > peak { peak = sm; }
-                    }
-                    let rms = (rms / (buf_sz as f32)).sqrt();
-                    pb.set_message(format!("{rms} {peak}"));
-                    pb.set_position((rms * 100.0) as u64);
-                    std::thread::sleep(std::time::Duration::from_millis(10));
-                }
-            });
+fn parse_align(args: &Value) -> Result<CmdAlign> {
+    let mut cmd = CmdAlign::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ref" => cmd.reference = as_channel(val)?,
+            "ch" => cmd.channels = as_channels(val)?,
+            "maxlag" => cmd.max_lag = as_f32(val)?,
+            _ => return Err(anyhow!("unknown key")),
         }
+        Ok(())
+    })?;
 
-        else if opt.scope.len() > 0 {
-            let args = lexpr::from_str(&opt.scope)?;
-            let scope_cmd = parse_scope(&args)?;
-            let channel_ct = scope_cmd.channels.len();
-            let scopectl = Arc::new(Scope::new());
-            {
-                let mut scope = scopectl.data.lock().unwrap();
-                for ch in &scope_cmd.channels {
-                    scope.push(ScopeChannel::new(&*format!("ch{}", *ch)));
-                }
-            }
-            let scopectl_p = scopectl.clone();
-            thread::spawn(move || {
-                loop {
-                    let mut buf: Vec<f32> = Vec::new();
-                    let buf_sz = 4096;
-                    loop {
-                        if buf.len() >= (buf_sz * channel_ct) {
-                            break;
-                        }
-                        if consumer.occupied_len() >= input_ch_ct {
-                            let mut frame = vec![0.0; input_ch_ct];
-                            consumer.pop_slice(&mut frame);
-                            for ch in &scope_cmd.channels {
-                                buf.push(frame[*ch as usize]);
-                            }
-                        }
-                    }
+    Ok(cmd)
+}
 
-                    let trigger_index = find_trigger(&buf, 0, channel_ct);
+fn parse_imd(args: &Value) -> Result<CmdImd> {
+    let mut cmd = CmdImd::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "test" => cmd.test = Some(Imd::from_name(as_name(val)?)?),
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
 
-                    // let mut rms: f32 = 0.0;
-                    // let mut peak: f32 = 0.0;
-                    // let mut i: u32 = 0;
-                    // let mut display_samples: Vec<(f32, f32)> = Vec::new();
-                    // for s in buf {
-                    //     rms += (s * s);
-                    //     let sm = s.abs();
-                    //     if sm > peak { peak = sm; }
-                    //     if i < 512 {
-                    //         let point: (f32, f32) = (((i as f32) / sample_rate), s);
-                    //         display_samples.push(point);
-                    //         // if i < 24 {
-                    //         //     println!("sample {} {} {}", i, point.0, point.1);
-                    //         // }
-                    //     }
-                    //     i = i + 1;
-                    // }
-                    // let rms = (rms / (buf_sz as f32)).sqrt();
+    Ok(cmd)
+}
 
-                    for ch in &scope_cmd.channels {
-                        scopectl_p.data.lock().unwrap()[*ch as usize] 
-                            = calc_scope_channel(&buf, *ch as usize, channel_ct, buf_sz, sample_rate, trigger_index);
-                        // data.samples = display_samples;
-                        // data.peak = peak;
-                        // data.rms = rms;
-                    }
-                    std::thread::sleep(std::time::Duration::from_millis(10));
-                }
-            });
-            run_scope(scopectl.clone()); // does not return
+fn parse_ir(args: &Value) -> Result<CmdIr> {
+    let mut cmd = CmdIr::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "out" => cmd.out = as_name(val)?.to_string(),
+            "harmonics" => cmd.harmonics = as_usize(val)?,
+            "len" => cmd.len = as_f32(val)?,
+            "tail" => cmd.tail = as_f32(val)?,
+            _ => return Err(anyhow!("unknown key")),
         }
-    }
+        Ok(())
+    })?;
+
+    Ok(cmd)
+}
 
-    if opt.dur == 0.0 {
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+fn parse_latency(args: &Value) -> Result<CmdLatency> {
+    let mut cmd = CmdLatency::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "reps" => cmd.reps = as_usize(val)?,
+            "max" => cmd.max = as_f32(val)?,
+            _ => return Err(anyhow!("unknown key")),
         }
-    } else {
-        std::thread::sleep(std::time::Duration::from_millis((1000.0 * opt.dur) as u64));
-    }
+        Ok(())
+    })?;
 
-    Ok(())
+    Ok(cmd)
 }
 
-fn find_trigger(buf: &[f32], trigger_ch: usize, ch_ct: usize) -> usize {
-    let mut prev_sample = buf[trigger_ch];
-    let mut i: usize = 0;
-    for frame in buf.chunks_exact(ch_ct) {
-        let s = frame[trigger_ch];
-        if (prev_sample <= 0.0) && (s > 0.0) {
-            return i;
+fn parse_freqresp(args: &Value) -> Result<CmdFreqresp> {
+    let mut cmd = CmdFreqresp::new();
+    for_plist(args, |key, val| {
+        match key {
+            "freqs" => cmd.tones = Tones::parse(val)?,
+            "band" => cmd.band = parse_band(val)?,
+            "settle" => cmd.settle = as_f32(val)?,
+            "dwell" => cmd.dwell = as_f32(val)?,
+            "ref" => cmd.reference = Some(as_channel(val)?),
+            "ch" => cmd.channels = as_channels(val)?,
+            "out" => cmd.out = as_name(val)?.to_string(),
+            _ => return Err(anyhow!("unknown key")),
         }
-        i += 1;
-        prev_sample = s;
+        Ok(())
+    })?;
+    if cmd.tones.freqs(cmd.band).is_empty() {
+        return Err(anyhow!("no frequencies to step through"));
     }
-    0
-}
 
-fn calc_scope_channel(buf: &[f32], ch: usize, ch_ct: usize, buf_sz: usize, sample_rate: f32, trigger_idx: usize) -> ScopeChannel {
-    let mut d = ScopeChannel::new("");
-    let mut i = 0;
-    let display_length = 512;
-    let last_sample_idx = trigger_idx + display_length;
-    for frame in buf.chunks_exact(ch_ct) {
-        let s = frame[ch];
-        d.rms += (s * s);
-        let sm = s.abs();
-        if sm > d.peak { d.peak = sm; }
-        if i >= trigger_idx && i < last_sample_idx {
-            let point: (f32, f32) = ((((i - trigger_idx) as f32) / sample_rate), s);
-            d.samples.push(point);
-            // if i < 24 {
-            //     println!("sample {} {} {}", i, point.0, point.1);
-            // }
-        }
-        i = i + 1;
-    }
-    d.rms = (d.rms / (buf_sz as f32)).sqrt();
-    d
+    Ok(cmd)
 }
 
-fn parse_input(args: &Value) -> Result<CmdInput> {
-    let mut cmd = CmdInput::new();
+fn parse_crosstalk(args: &Value) -> Result<CmdCrosstalk> {
+    let mut cmd = CmdCrosstalk::new();
     for_plist(args, |key, val| {
         match key {
-            "ch" => {
-                for v in val.list_iter().unwrap() {
-                    match v {
-                        Value::Number(v) => cmd.channels.push(v.as_u64().unwrap() as u8),
-                        _ => ()
-                    }
-                }
-            },
-            _ => ()
+            "drive" => cmd.drive = as_channels(val)?,
+            "ch" => cmd.channels = as_channels(val)?,
+            "freqs" => cmd.tones = Tones::parse(val)?,
+            "band" => cmd.band = parse_band(val)?,
+            "settle" => cmd.settle = as_f32(val)?,
+            "dwell" => cmd.dwell = as_f32(val)?,
+            "out" => cmd.out = as_name(val)?.to_string(),
+            _ => return Err(anyhow!("unknown key")),
         }
-    });
+        Ok(())
+    })?;
+    if cmd.tones.freqs(cmd.band).is_empty() {
+        return Err(anyhow!("no frequencies to drive"));
+    }
 
     Ok(cmd)
 }
 
-fn parse_scope(args: &Value) -> Result<CmdScope> {
-    let mut cmd = CmdScope::new();
+fn parse_multitone(args: &Value) -> Result<CmdMultitone> {
+    let mut cmd = CmdMultitone::new();
     for_plist(args, |key, val| {
         match key {
-            "ch" => {
-                for v in val.list_iter().unwrap() {
-                    match v {
-                        Value::Number(v) => cmd.channels.push(v.as_u64().unwrap() as u8),
-                        _ => ()
-                    }
-                }
-            },
-            _ => ()
+            "ch" => cmd.channels = as_channels(val)?,
+            "periods" => cmd.periods = as_usize(val)?,
+            "out" => cmd.out = as_name(val)?.to_string(),
+            _ => return Err(anyhow!("unknown key")),
         }
-    });
+        Ok(())
+    })?;
+    if cmd.periods < 2 {
+        return Err(anyhow!("need at least 2 periods to tell noise from distortion"));
+    }
 
     Ok(cmd)
 }
@@ -383,26 +2304,68 @@ fn parse_scope(args: &Value) -> Result<CmdScope> {
 fn parse_sinout(args: &Value) -> Result<CmdSinout> {
     let mut cmd = CmdSinout::new();
     let mut channels: Vec<u8> = Vec::new();
+    let mut ampl = None;
+    // lists among the keys and values describe channels of their own, e.g.
+    // (:wave square (:ch 0 :freq 1000) (:ch 1 :freq 1000 :phase 90))
+    let mut common = Vec::new();
+    let mut channel_args = Vec::new();
+    let mut items = args.list_iter().ok_or_else(|| anyhow!("expected a list of keys and values, got {}", args))?;
+    while let Some(item) = items.next() {
+        if item.is_list() {
+            channel_args.push(item);
+        } else {
+            common.push(item.clone());
+            common.extend(items.next().cloned());
+        }
+    }
+    let args = &Value::list(common);
     for_plist(args, |key, val| {
         match key {
-            "freq" => cmd.freq = val.as_f64().unwrap() as f32,
-            "ampl" => cmd.ampl = val.as_f64().unwrap() as f32,
-            "dur" => cmd.dur = val.as_f64().unwrap() as f32,
-            "ch" => {
-                for v in val.list_iter().unwrap() {
-                    match v {
-                        Value::Number(v) => channels.push(v.as_u64().unwrap() as u8),
-                        _ => ()
-                    }
+            "freq" => cmd.freq = as_f32(val)?,
+            "ampl" => ampl = Some(as_gain(val)?), // 0.5 or -6dB
+            "dur" => cmd.dur = as_f32(val)?,
+            "ch" => channels = as_channels(val)?,
+            "burst" => cmd.burst = match as_name(val)? {
+                "impulse" => Some(Burst::Impulse),
+                "mls" => Some(Burst::Mls),
+                b => return Err(anyhow!("unknown burst {}", b)),
+            },
+            "period" => cmd.period = as_f32(val)?,
+            "wave" => cmd.wave = Waveform::from_name(as_name(val)?)?,
+            "duty" => cmd.duty = as_gain(val)?, // 0.25 or 25%
+            "bandlimit" => cmd.band_limit = BandLimit::from_name(as_name(val)?)?,
+            "noise" => cmd.noise = Some(NoiseKind::from_name(as_name(val)?)?),
+            "seed" => cmd.seed = as_usize(val)? as u64,
+            "multitone" => cmd.tones = Some(Tones::parse(val)?),
+            "fft" => cmd.fft = as_usize(val)?,
+            "phases" => cmd.phases = Phases::from_name(as_name(val)?)?,
+            "imd" => cmd.imd = Some(Imd::from_name(as_name(val)?)?),
+            "band" => cmd.band = parse_band(val)?,
+            "sweep" => {
+                let f: Vec<f32> = match val.list_iter() {
+                    Some(i) => i.map(as_f32).collect::<Result<_>>()?,
+                    None => Vec::new(),
+                };
+                let [start, end] = f[..] else {
+                    return Err(anyhow!("expected (start end) frequencies"));
+                };
+                // the sweep rate is ln(end/start)
+                if !(start > 0.0 && start < end) {
+                    return Err(anyhow!("sweep needs 0 < start < end, got {} to {}", start, end));
                 }
+                cmd.sweep = Some((start, end));
             },
-            _ => ()
+            _ => return Err(anyhow!("unknown key")),
         }
-    });
+        Ok(())
+    })?;
+    // full scale noise would clip most of the time
+    let default_ampl = if cmd.noise.is_some() { NOISE_AMPL } else { cmd.ampl };
+    cmd.ampl = ampl.unwrap_or(default_ampl);
     // set up channels vector
     // it is a list of gains, corresponding to each channel.
     // user passes a list of channel numbers, so set each of these to 1 and leave the rest at 0.
-    if channels.len() > 0 {
+    if !channels.is_empty() {
         channels.sort();
         let lastch = channels[channels.len() - 1];
         cmd.channels.resize((lastch + 1) as usize, 0.0);
@@ -410,64 +2373,440 @@ fn parse_sinout(args: &Value) -> Result<CmdSinout> {
             cmd.channels[ch as usize] = 1.0;
         }
     }
+    if cmd.sweep.is_some() && cmd.dur <= 0.0 {
+        return Err(anyhow!("sweep needs a dur"));
+    }
+    if cmd.burst.is_some() && cmd.period <= 0.0 {
+        return Err(anyhow!("burst period must be more than 0"));
+    }
+    if !channel_args.is_empty() {
+        if !cmd.channels.is_empty() {
+            return Err(anyhow!("give :ch in each channel's list, not for them all"));
+        }
+        if !cmd.periodic() {
+            return Err(anyhow!("channels of their own can only play a periodic wave"));
+        }
+        for a in channel_args {
+            let tone = parse_channel_tone(a, &cmd)?;
+            if cmd.per_channel.iter().any(|t| t.ch == tone.ch) {
+                return Err(anyhow!("ch {} given twice", tone.ch));
+            }
+            cmd.per_channel.push(tone);
+        }
+        // what the analysis expects to find, as far as one frequency can say
+        if let Some(t) = cmd.per_channel.iter().find(|t| t.on) {
+            cmd.freq = t.freq;
+        }
+    }
     Ok(cmd)
 }
 
-fn for_plist<F>(plist: &Value, mut func: F)
-    where F: FnMut(&str, &Value)
-{
-    let mut i = plist.list_iter().unwrap();
-    loop {
-        match i.next() {
-            Some(key) => {
-                match *key {
-                    Value::Symbol(_) => {
-                        match i.next() {
-                            Some(val) => func(key.as_symbol().unwrap(), val),
-                            None => break
-                        }
-                    },
-                    _ => break
+// a channel's own (:ch 1 :freq 1000 :ampl -6dBFS :phase 90 :on #t); what it
+// leaves out comes from the rest of the sinout
+fn parse_channel_tone(args: &Value, common: &CmdSinout) -> Result<ChannelTone> {
+    let mut ch = None;
+    let mut tone = ChannelTone { ch: 0, freq: common.freq, ampl: common.ampl, phase: 0.0, on: true };
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => ch = Some(as_channel(val)?),
+            "freq" => tone.freq = as_f32(val)?,
+            "ampl" => tone.ampl = as_gain(val)?,
+            "phase" => tone.phase = as_f32(val)?,
+            "on" => tone.on = as_bool(val)?,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    tone.ch = ch.ok_or_else(|| anyhow!("each channel's list needs a :ch"))?;
+    Ok(tone)
+}
+
+fn parse_measure(args: &Value) -> Result<CmdMeasure> {
+    let Some((metric, rest)) = args.as_pair() else {
+        return Err(anyhow!("expected a metric"));
+    };
+    let metric = match as_name(metric)? {
+        "rms" => Metric::Rms,
+        "peak" => Metric::Peak,
+        "thd" => Metric::Thd,
+        "imd" => Metric::Imd,
+        m => return Err(anyhow!("unknown metric {}", m)),
+    };
+    let mut cmd = CmdMeasure::new(metric);
+    for_plist(rest, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "harmonics" => cmd.harmonics = as_usize(val)?,
+            "len" => cmd.len = as_f32(val)?,
+            "test" => cmd.imd = Some(Imd::from_name(as_name(val)?)?),
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+
+    Ok(cmd)
+}
+
+fn parse_expect(args: &Value) -> Result<CmdExpect> {
+    let Some((metric, rest)) = args.as_pair() else {
+        return Err(anyhow!("expected a metric"));
+    };
+    let metric = as_name(metric)?.to_string();
+    let kind = match metric.as_str() {
+        "rms" => Metric::Rms,
+        "peak" => Metric::Peak,
+        "thd" | "thd+n" | "sinad" | "freq" => Metric::Thd,
+        "imd" | "imd2" | "imd3" => Metric::Imd,
+        m => return Err(anyhow!("unknown metric {}", m)),
+    };
+    let mut cmd = CmdExpect { measure: CmdMeasure::new(kind), metric, min: None, max: None, name: None };
+    // freq limits are in Hz, everything else is a level
+    let limit = |val: &Value| if cmd.metric == "freq" { as_f32(val) } else { as_db(val) };
+    let (mut min, mut max) = (None, None);
+    for_plist(rest, |key, val| {
+        match key {
+            "ch" => cmd.measure.channels = as_channels(val)?,
+            "harmonics" => cmd.measure.harmonics = as_usize(val)?,
+            "len" => cmd.measure.len = as_f32(val)?,
+            "test" => cmd.measure.imd = Some(Imd::from_name(as_name(val)?)?),
+            "min" => min = Some(limit(val)?),
+            "max" => max = Some(limit(val)?),
+            "name" => cmd.name = Some(as_name(val)?.to_string()),
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    if min.is_none() && max.is_none() {
+        return Err(anyhow!("expected a min or max"));
+    }
+    cmd.min = min;
+    cmd.max = max;
+
+    Ok(cmd)
+}
+
+fn parse_play(args: &Value) -> Result<CmdPlay> {
+    let mut cmd = CmdPlay::new();
+    for_plist(args, |key, val| {
+        match key {
+            "file" => cmd.file = as_name(val)?.to_string(),
+            "loop" => cmd.looped = as_bool(val)?,
+            // ((from to gain) ...), gain optional
+            "route" => {
+                let routes = val.list_iter().ok_or_else(|| anyhow!("expected a list of (from to gain) routes"))?;
+                for route in routes {
+                    let parts: Vec<&Value> = route.list_iter().map(|i| i.collect()).unwrap_or_default();
+                    let (from, to, gain) = match parts[..] {
+                        [from, to] => (from, to, None),
+                        [from, to, gain] => (from, to, Some(gain)),
+                        _ => return Err(anyhow!("expected (from to gain), got {}", route)),
+                    };
+                    cmd.routes.push(Route {
+                        from: as_usize(from)?,
+                        to: as_usize(to)?,
+                        gain: gain.map(as_gain).transpose()?.unwrap_or(1.0),
+                    });
                 }
             },
-            None => break
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    if cmd.file.is_empty() {
+        return Err(anyhow!("expected a file"));
+    }
+
+    Ok(cmd)
+}
+
+fn parse_record(args: &Value) -> Result<CmdRecord> {
+    let mut cmd = CmdRecord::new();
+    for_plist(args, |key, val| {
+        match key {
+            "ch" => cmd.channels = as_channels(val)?,
+            "file" => cmd.file = as_name(val)?.to_string(),
+            "dur" => cmd.dur = as_f32(val)?,
+            "format" => cmd.format = PcmFormat::from_name(as_name(val)?)?,
+            "split" => cmd.split = as_bool(val)?,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    })?;
+    if cmd.file.is_empty() {
+        return Err(anyhow!("expected a file"));
+    }
+
+    Ok(cmd)
+}
+
+fn parse_report(args: &Value) -> Result<CmdReport> {
+    let mut file = String::new();
+    let mut format = None;
+    for_plist(args, |key, val| {
+        match key {
+            "file" => file = as_name(val)?.to_string(),
+            "format" => format = Some(report::Format::from_name(as_name(val)?)?),
+            _ => return Err(anyhow!("unknown key")),
         }
+        Ok(())
+    })?;
+    if file.is_empty() {
+        return Err(anyhow!("expected a file"));
     }
+    // go by the file extension if the format isn't given
+    let format = match format {
+        Some(f) => f,
+        None => report::Format::from_path(&file)?,
+    };
+
+    Ok(CmdReport { file, format })
 }
 
-fn parse_cmd(cmd: &Value, args: &Value) -> Result<Command> {
+fn parse_cmd(cmd: &str, args: &Value) -> Result<Command> {
     match cmd {
-        Value::Symbol(s) => match cmd.as_str().unwrap() {
-            "sinout" => Ok(Command::Sinout(parse_sinout(args).unwrap())),
-            //"fftmon" => Ok(parse_fftmon(args)),
-            _ => Err(anyhow!("unknown command {}", *s))
+        "sinout" => Ok(Command::Sinout(parse_sinout(args)?)),
+        "play" => Ok(Command::Play(parse_play(args)?)),
+        "stop" => Ok(Command::Stop),
+        "input" => Ok(Command::Input(parse_input(args)?)),
+        "measure" => Ok(Command::Measure(parse_measure(args)?)),
+        "expect" => Ok(Command::Expect(parse_expect(args)?)),
+        "wait" => match args.as_pair() {
+            Some((secs, _)) => Ok(Command::Wait(as_f32(secs)?)),
+            None => Err(anyhow!("expected a time in seconds")),
         },
-        _ => Err(anyhow!("bad token {}", cmd))
+        //"fftmon" => Ok(parse_fftmon(args)),
+        _ => Err(anyhow!("unknown command")),
+    }
+}
+
+fn start_play(device: &cpal::Device, config: &cpal::SupportedStreamConfig, cmd: &CmdPlay, clock: SharedClock) -> Result<cpal::Stream> {
+    let file = AudioFile::open(&cmd.file)?;
+    if file.sample_rate != config.sample_rate().0 {
+        return Err(anyhow!("{} is {} Hz but the device runs at {} Hz", cmd.file, file.sample_rate, config.sample_rate().0));
+    }
+    let out_ch_ct = config.channels() as usize;
+    let routes = if cmd.routes.is_empty() {
+        (0..file.channels.min(out_ch_ct)).map(|ch| Route { from: ch, to: ch, gain: 1.0 }).collect()
+    } else {
+        cmd.routes.clone()
+    };
+    for route in &routes {
+        if route.from >= file.channels {
+            return Err(anyhow!("{} has no ch {}", cmd.file, route.from));
+        }
+        if route.to >= out_ch_ct {
+            return Err(anyhow!("output has no ch {}", route.to));
+        }
+    }
+    println!("playing {} ({} ch, {:.1} s){}", cmd.file, file.channels,
+        file.frames() as f32 / file.sample_rate as f32, if cmd.looped { ", looped" } else { "" });
+
+    match config.sample_format() {
+        cpal::SampleFormat::F32 => run_play::<f32>(device, &config.clone().into(), file, routes, cmd.looped, clock),
+        sample_format => Err(anyhow!("Unsupported sample format '{sample_format}'")),
     }
 }
 
-pub fn run_sinout<T>(device: &cpal::Device, config: &cpal::StreamConfig, params: CmdSinout) -> Result<cpal::Stream, anyhow::Error>
+fn run_play<T>(device: &cpal::Device, config: &cpal::StreamConfig, file: AudioFile, routes: Vec<Route>, looped: bool, clock: SharedClock) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>
+{
+    let channels = config.channels as usize;
+    let frames = file.frames();
+    let mut pos = 0;
+    let mut frames_written: u64 = 0;
+    let mut mix = vec![0.0f32; channels];
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            *clock.lock().unwrap() = Some(StreamClock { frame: frames_written, at: info.timestamp().callback });
+            frames_written += (data.len() / channels) as u64;
+            for frame in data.chunks_mut(channels) {
+                mix.fill(0.0);
+                if pos < frames {
+                    let input = &file.samples[pos * file.channels..(pos + 1) * file.channels];
+                    for route in &routes {
+                        mix[route.to] += input[route.from] * route.gain;
+                    }
+                    pos += 1;
+                    if pos == frames && looped {
+                        pos = 0;
+                    }
+                }
+                for (sample, value) in frame.iter_mut().zip(&mix) {
+                    *sample = value.to_sample();
+                }
+            }
+        },
+        err_fn,
+        None,
+    )?;
+
+    stream.play()?;
+
+    Ok(stream)
+}
+
+// fills in one frame of output each call
+type FrameSource = Box<dyn FnMut(&mut [f32]) + Send>;
+
+fn run_sinout<T>(device: &cpal::Device, config: &cpal::StreamConfig, params: CmdSinout, clock: SharedClock) -> Result<cpal::Stream, anyhow::Error>
 where
     T: SizedSample + FromSample<f32>
 {
     let sample_rate = config.sample_rate.0 as f32;
     let channels = config.channels as usize;
 
-    // Produce a sinusoid.
-    let mut sample_clock = 0f32;
-    let mut next_value = move || {
-        sample_clock = (sample_clock + 1.0) % sample_rate;
-        (sample_clock * params.freq * 2.0 * std::f32::consts::PI / sample_rate).sin() * params.ampl
+    let mut next_value: Box<dyn FnMut() -> f32 + Send> = match (params.sweep, params.burst) {
+        // Play the sweep once, after a short silence, then stay quiet.
+        (Some((start, end)), _) => {
+            let mut stimulus = vec![0.0; (SWEEP_PREROLL * sample_rate) as usize];
+            stimulus.extend(Sweep::new(start, end, params.dur, sample_rate).samples());
+            let mut i = 0;
+            Box::new(move || {
+                let s = stimulus.get(i).map_or(0.0, |s| s * params.ampl);
+                i += 1;
+                s
+            })
+        },
+        // Repeat the burst at the start of every period. Burst k starts at frame k * period.
+        (None, Some(burst)) => {
+            let template = burst.samples()?;
+            let period_frames = (params.period * sample_rate) as usize;
+            let mut i = 0;
+            Box::new(move || {
+                let s = template.get(i % period_frames).map_or(0.0, |s| s * params.ampl);
+                i += 1;
+                s
+            })
+        },
+        // Two tones, in the test's ratio, peaking together at ampl.
+        (None, None) if params.imd.is_some() => {
+            let (f1, f2, ratio) = params.imd.unwrap().tones();
+            let mut low = Oscillator::new(Waveform::Sine, f1, sample_rate, 0.5, BandLimit::None);
+            let mut high = Oscillator::new(Waveform::Sine, f2, sample_rate, 0.5, BandLimit::None);
+            let mut frames_left = (params.dur * sample_rate) as u64;
+            Box::new(move || {
+                if params.dur > 0.0 {
+                    if frames_left == 0 {
+                        return 0.0;
+                    }
+                    frames_left -= 1;
+                }
+                (low.next_sample() * ratio + high.next_sample()) / (ratio + 1.0) * params.ampl
+            })
+        },
+        // Loop one period of the multitone.
+        (None, None) if params.tones.is_some() => {
+            let mt = params.multitone(sample_rate)?.unwrap();
+            println!("multitone: {} tones, crest factor {:.2} dB", mt.bins.len(), mt.crest_factor_db());
+            let period = mt.samples();
+            let mut frames_left = (params.dur * sample_rate) as u64;
+            let mut i = 0;
+            Box::new(move || {
+                if params.dur > 0.0 {
+                    if frames_left == 0 {
+                        return 0.0;
+                    }
+                    frames_left -= 1;
+                }
+                let s = period[i % period.len()] * params.ampl;
+                i += 1;
+                s
+            })
+        },
+        // Produce noise, or a periodic waveform, a sine unless told otherwise.
+        (None, None) => {
+            let mut osc: Box<dyn Generator> = match params.noise {
+                Some(kind) => Box::new(Noise::new(kind, params.band, sample_rate, params.seed)),
+                None => Box::new(Oscillator::new(params.wave, params.freq, sample_rate, params.duty, params.band_limit)),
+            };
+            let mut frames_left = (params.dur * sample_rate) as u64;
+            Box::new(move || {
+                if params.dur > 0.0 {
+                    if frames_left == 0 {
+                        return 0.0;
+                    }
+                    frames_left -= 1;
+                }
+                osc.next_sample() * params.ampl
+            })
+        },
+    };
+
+    let mut next_frame: FrameSource = if let Some((steps, step)) = params.steps.clone() {
+        // Hold each step's tone in turn, on that step's channels, then stay quiet.
+        let step_frames = ((step * sample_rate) as u64).max(1);
+        // band limited tables take a while to build, so build them all before the stream starts
+        let mut oscs: Vec<Oscillator> = steps.iter()
+            .map(|s| Oscillator::new(params.wave, s.freq, sample_rate, params.duty, params.band_limit))
+            .collect();
+        let gains = params.channels.clone();
+        let mut frame_ct = 0;
+        Box::new(move |frame| {
+            frame.fill(0.0);
+            let i = (frame_ct / step_frames) as usize;
+            if i >= steps.len() {
+                return;
+            }
+            // carry on from where the last step left off
+            if i > 0 && frame_ct % step_frames == 0 {
+                let phase = oscs[i - 1].phase();
+                oscs[i].set_phase(phase);
+            }
+            frame_ct += 1;
+            let value = oscs[i].next_sample() * params.ampl;
+            let gains = if steps[i].gains.is_empty() { &gains } else { &steps[i].gains };
+            for (s, g) in frame.iter_mut().zip(gains) {
+                *s = value * g;
+            }
+        })
+    } else if params.per_channel.is_empty() {
+        // the one value, scaled for each channel
+        let gains = params.channels.clone();
+        Box::new(move |frame| {
+            let value = next_value();
+            for (i, s) in frame.iter_mut().enumerate() {
+                *s = gains.get(i).map_or(0.0, |g| value * g);
+            }
+        })
+    } else {
+        // Each channel with a wave of its own.
+        let mut oscs: Vec<(usize, Oscillator, f32)> = params.per_channel.iter()
+            .filter(|t| t.on)
+            .map(|t| {
+                let mut osc = Oscillator::new(params.wave, t.freq, sample_rate, params.duty, params.band_limit);
+                osc.set_phase(t.phase);
+                (t.ch as usize, osc, t.ampl)
+            })
+            .collect();
+        let mut frames_left = (params.dur * sample_rate) as u64;
+        Box::new(move |frame| {
+            frame.fill(0.0);
+            if params.dur > 0.0 {
+                if frames_left == 0 {
+                    return;
+                }
+                frames_left -= 1;
+            }
+            for (ch, osc, ampl) in oscs.iter_mut() {
+                frame[*ch] = osc.next_sample() * *ampl;
+            }
+        })
     };
 
     let err_fn = |err| eprintln!("an error occurred on stream: {err}");
 
-    let params2 = params.clone();
+    let mut frame = vec![0.0; channels];
+    let mut frames_written: u64 = 0;
 
     let stream = device.build_output_stream(
         config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            sinout_cb(data, channels, &params2, &mut next_value)
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            *clock.lock().unwrap() = Some(StreamClock { frame: frames_written, at: info.timestamp().callback });
+            frames_written += (data.len() / channels) as u64;
+            sinout_cb(data, &mut frame, &mut next_frame)
         },
         err_fn,
         None,
@@ -478,20 +2817,15 @@ where
     Ok(stream)
 }
 
-fn sinout_cb<T>(output: &mut [T], channels: usize, params: &CmdSinout, next_sample: &mut dyn FnMut() -> f32)
+fn sinout_cb<T>(output: &mut [T], frame: &mut [f32], next_frame: &mut dyn FnMut(&mut [f32]))
 where
     T: Sample + FromSample<f32>,
 {
     //println!("sinout cb {}", output.len());
-    for frame in output.chunks_mut(channels) {
-        //let value: T = T::from_sample(next_sample());
-        let value = next_sample();
-        let mut gaini = params.channels.iter();
-        for sample in frame.iter_mut() {
-            match gaini.next() {
-                None => *sample = (0.0).to_sample(),
-                Some(g) => *sample = (value * g).to_sample()
-            }
+    for out in output.chunks_mut(frame.len()) {
+        next_frame(frame);
+        for (sample, value) in out.iter_mut().zip(frame.iter()) {
+            *sample = value.to_sample();
         }
     }
 }
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+use crate::{analysis, spectrum};
+
+/// Maximum length sequence of length 2^order - 1, as +/-1 samples.
+/// Supports orders 8 through 16.
+pub fn mls(order: u32) -> Result<Vec<f32>> {
+    // feedback taps for a maximal length fibonacci lfsr, 1-indexed
+    let taps: &[u32] = match order {
+        8 => &[8, 6, 5, 4],
+        9 => &[9, 5],
+        10 => &[10, 7],
+        11 => &[11, 9],
+        12 => &[12, 11, 10, 4],
+        13 => &[13, 12, 11, 8],
+        14 => &[14, 13, 12, 2],
+        15 => &[15, 14],
+        16 => &[16, 15, 13, 4],
+        _ => return Err(anyhow!("no mls of order {}, only 8 to 16", order)),
+    };
+    let order = taps[0];
+    let mut state: u32 = 1;
+    Ok((0..(1u32 << order) - 1)
+        .map(|_| {
+            let out = state & 1;
+            let bit = taps.iter().fold(0, |acc, t| acc ^ (state >> (order - t)));
+            state = (state >> 1) | ((bit & 1) << (order - 1));
+            if out == 1 { 1.0 } else { -1.0 }
+        })
+        .collect())
+}
+
+/// Cross-correlation of `x` against `template`: r[i] = sum_j x[i + j] * template[j],
+/// for every i in x (the template runs off the end of x for the last few).
+pub fn cross_correlate(x: &[f32], template: &[f32]) -> Vec<f32> {
+    if x.is_empty() || template.is_empty() {
+        return Vec::new();
+    }
+    let reversed: Vec<f32> = template.iter().rev().copied().collect();
+    let full = spectrum::convolve(x, &reversed);
+    full[template.len() - 1..template.len() - 1 + x.len()].to_vec()
+}
+
+/// Phase correlation of two channels, as on a stereo correlation meter: +1 when
+/// they move together, 0 when unrelated, -1 when one is the other inverted.
+pub fn phase_correlation(x: &[f32], y: &[f32]) -> f32 {
+    let (mut xy, mut xx, mut yy) = (0.0f64, 0.0f64, 0.0f64);
+    for (a, b) in x.iter().zip(y) {
+        let (a, b) = (*a as f64, *b as f64);
+        xy += a * b;
+        xx += a * a;
+        yy += b * b;
+    }
+    if xx == 0.0 || yy == 0.0 { 0.0 } else { (xy / (xx * yy).sqrt()) as f32 }
+}
+
+/// Largest |r| as (fractional index, signed value at the peak bin).
+/// The index is refined by fitting a parabola through the neighbouring points.
+pub fn peak(r: &[f32]) -> (f32, f32) {
+    if r.is_empty() {
+        return (0.0, 0.0);
+    }
+    let i = r.iter().enumerate()
+        .fold(0, |best, (i, v)| if v.abs() > r[best].abs() { i } else { best });
+    let mut pos = i as f32;
+    if i > 0 && i + 1 < r.len() {
+        let (a, b, c) = (r[i - 1].abs(), r[i].abs(), r[i + 1].abs());
+        let denom = a - 2.0 * b + c;
+        if denom != 0.0 {
+            pos += 0.5 * (a - c) / denom;
+        }
+    }
+    (pos, r[i])
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Alignment {
+    /// samples by which the channel lags the reference; negative if it leads
+    pub delay: f32,
+    /// normalised correlation at the delay, -1 to 1; negative when the polarity is inverted
+    pub correlation: f32,
+    /// strongest frequency in the reference, Hz
+    pub freq: f32,
+    /// phase of the channel relative to the reference at `freq`, degrees
+    pub phase_deg: f32,
+}
+
+impl Alignment {
+    pub fn inverted(&self) -> bool {
+        self.correlation < 0.0
+    }
+}
+
+/// Compare `x` with `reference`, looking for a delay of up to `max_lag` samples
+/// either way. The delay is only unambiguous for broadband signals; with a
+/// steady tone every period correlates as well, and the phase says more.
+pub fn align(reference: &[f32], x: &[f32], sample_rate: f32, max_lag: usize) -> Alignment {
+    let n = reference.len().min(x.len());
+    if n < 2 {
+        return Alignment::default();
+    }
+    let (reference, x) = (&reference[..n], &x[..n]);
+    // index n - 1 of the full correlation is no delay
+    let reversed: Vec<f32> = reference.iter().rev().copied().collect();
+    let full = spectrum::convolve(x, &reversed);
+    let max_lag = max_lag.min(n - 1);
+    let (pos, value) = peak(&full[n - 1 - max_lag..=n - 1 + max_lag]);
+    let energy = (reference.iter().map(|s| s * s).sum::<f32>() * x.iter().map(|s| s * s).sum::<f32>()).sqrt();
+
+    let power: Vec<f32> = spectrum::amplitude_spectrum(reference, spectrum::Window::Hann).iter().map(|a| a * a).collect();
+    let freq = analysis::peak_bin(&power) as f32 * sample_rate / n as f32;
+    let (_, ref_phase) = analysis::lock_in(reference, sample_rate, freq);
+    let (_, phase) = analysis::lock_in(x, sample_rate, freq);
+    let deg = (phase - ref_phase).to_degrees();
+
+    Alignment {
+        delay: pos - max_lag as f32,
+        correlation: if energy > 0.0 { value / energy } else { 0.0 },
+        freq,
+        phase_deg: deg - 360.0 * (deg / 360.0).round(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mls_has_the_full_period() {
+        for order in 8..=16 {
+            let seq = mls(order).unwrap();
+            let n = (1usize << order) - 1;
+            assert_eq!(seq.len(), n, "order {order}");
+            // a maximal sequence goes through every nonzero state once, so every
+            // `order` bits long window of it, wrapping round, is a different one
+            let bit = |i: usize| (seq[i % n] > 0.0) as usize;
+            let mut seen = vec![false; n + 1];
+            for i in 0..n {
+                let window = (0..order as usize).fold(0, |w, j| (w << 1) | bit(i + j));
+                assert!(window != 0 && !seen[window], "order {order} repeats after {i}");
+                seen[window] = true;
+            }
+        }
+        assert!(mls(7).is_err());
+        assert!(mls(17).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+// Second order IIR filter sections.
+
+use std::f64::consts::PI;
+
+/// q of the two sections of a 4th order butterworth
+pub const BUTTERWORTH_4: [f64; 2] = [0.541_196_1, 1.306_563];
+
+/// A biquad in direct form I.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// From coefficients b0, b1, b2 and a1, a2, normalised so a0 is 1.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    /// RBJ cookbook high pass.
+    pub fn high_pass(freq: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::rbj(freq, q, sample_rate);
+        Self::normalised([(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    /// RBJ cookbook low pass.
+    pub fn low_pass(freq: f64, q: f64, sample_rate: f64) -> Self {
+        let (cos, alpha) = Self::rbj(freq, q, sample_rate);
+        Self::normalised([(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    /// The same section with its gain multiplied by `gain`.
+    pub fn scaled(&self, gain: f64) -> Self {
+        Self::new(self.b.map(|b| b * gain), self.a)
+    }
+
+    fn rbj(freq: f64, q: f64, sample_rate: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * freq / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn normalised(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new([b[0] / a[0], b[1] / a[0], b[2] / a[0]], [a[1] / a[0], a[2] / a[0]])
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
@@ -0,0 +1,347 @@
+// Signal sources for the output.
+//
+// Square, saw and pulse waves jump, and triangles turn sharp corners; sampled
+// naively their harmonics run past nyquist and alias back into the band. They
+// can be band-limited with polyblep/polyblamp corrections, or built additively
+// from the harmonics below nyquist.
+
+use anyhow::{anyhow, Result};
+use crate::filter::{Biquad, BUTTERWORTH_4};
+use std::f64::consts::PI;
+
+/// Something that produces output samples one at a time.
+pub trait Generator: Send {
+    fn next_sample(&mut self) -> f32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Triangle,
+    Saw,
+    /// a square wave with variable duty cycle
+    Pulse,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "sine" => Ok(Waveform::Sine),
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "saw" => Ok(Waveform::Saw),
+            "pulse" => Ok(Waveform::Pulse),
+            _ => Err(anyhow!("unknown waveform {}", name)),
+        }
+    }
+}
+
+/// How to keep harmonics above nyquist out of a waveform. Additive synthesis is
+/// the cleanest; like any band-limited square its peaks would overshoot by up
+/// to about 18%, so its tables are scaled down to peak at 1.0.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum BandLimit {
+    None,
+    PolyBlep,
+    #[default]
+    Additive,
+}
+
+impl BandLimit {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "none" => Ok(BandLimit::None),
+            "polyblep" => Ok(BandLimit::PolyBlep),
+            "additive" => Ok(BandLimit::Additive),
+            _ => Err(anyhow!("unknown band limiting {}", name)),
+        }
+    }
+}
+
+// samples in one cycle of an additive wavetable
+const TABLE_LEN: usize = 4096;
+
+/// A periodic waveform at unit amplitude.
+///
+/// Over one cycle, with phase t from 0 to 1: the sine is sin(2 pi t), the saw
+/// ramps from -1 up to 1, the pulse is 1 for the first `duty` of the cycle and
+/// -1 for the rest, and the triangle goes from -1 at t = 0 to 1 at t = 0.5.
+pub struct Oscillator {
+    waveform: Waveform,
+    duty: f64,
+    band_limit: BandLimit,
+    dt: f64, // cycles per sample
+    phase: f64,
+    table: Vec<f32>,
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, freq: f32, sample_rate: f32, duty: f32, band_limit: BandLimit) -> Self {
+        let duty = match waveform {
+            Waveform::Square => 0.5,
+            _ => (duty as f64).clamp(0.0, 1.0),
+        };
+        let dt = freq as f64 / sample_rate as f64;
+        let mut osc = Self { waveform, duty, band_limit, dt, phase: 0.0, table: Vec::new() };
+        if band_limit == BandLimit::Additive && waveform != Waveform::Sine {
+            osc.table = osc.additive_table();
+        }
+        osc
+    }
+
+    /// Jump to a point in the cycle, in degrees.
+    pub fn set_phase(&mut self, degrees: f32) {
+        self.phase = (degrees as f64 / 360.0).rem_euclid(1.0);
+    }
+
+    /// Where in the cycle the oscillator is, in degrees.
+    pub fn phase(&self) -> f32 {
+        (self.phase * 360.0) as f32
+    }
+
+    fn naive(&self, t: f64) -> f64 {
+        match self.waveform {
+            Waveform::Sine => (2.0 * PI * t).sin(),
+            Waveform::Saw => 2.0 * t - 1.0,
+            Waveform::Square | Waveform::Pulse => if t < self.duty { 1.0 } else { -1.0 },
+            Waveform::Triangle => if t < 0.5 { 4.0 * t - 1.0 } else { 3.0 - 4.0 * t },
+        }
+    }
+
+    fn poly_blep(&self, t: f64) -> f64 {
+        let dt = self.dt;
+        match self.waveform {
+            Waveform::Sine => self.naive(t),
+            // steps of -2 at t = 0, and for pulses +2 at t = 0 and -2 at t = duty
+            Waveform::Saw => self.naive(t) - blep(t, dt),
+            Waveform::Square | Waveform::Pulse => self.naive(t) + blep(t, dt) - blep((t + 1.0 - self.duty).fract(), dt),
+            // the slope changes by +8 cycles^-1 at t = 0 and -8 at t = 0.5
+            Waveform::Triangle => self.naive(t) + 8.0 * dt * (blamp(t, dt) - blamp((t + 0.5).fract(), dt)),
+        }
+    }
+
+    // one cycle summed from the fourier series, up to nyquist
+    fn additive_table(&self) -> Vec<f32> {
+        let harmonics = ((0.5 / self.dt) as usize).clamp(1, TABLE_LEN / 2);
+        let d = self.duty;
+        let mut table = (0..TABLE_LEN)
+            .map(|i| {
+                let t = i as f64 / TABLE_LEN as f64;
+                let sum: f64 = (1..=harmonics)
+                    .map(|k| {
+                        let k = k as f64;
+                        match self.waveform {
+                            Waveform::Sine => if k == 1.0 { (2.0 * PI * t).sin() } else { 0.0 },
+                            Waveform::Saw => -2.0 / (PI * k) * (2.0 * PI * k * t).sin(),
+                            Waveform::Square | Waveform::Pulse =>
+                                4.0 / (PI * k) * (PI * k * d).sin() * (2.0 * PI * k * (t - d / 2.0)).cos(),
+                            Waveform::Triangle => if k % 2.0 == 1.0 {
+                                -8.0 / (PI * PI * k * k) * (2.0 * PI * k * t).cos()
+                            } else {
+                                0.0
+                            },
+                        }
+                    })
+                    .sum();
+                // pulses sit at the mean of their two levels
+                let dc = match self.waveform {
+                    Waveform::Square | Waveform::Pulse => 2.0 * d - 1.0,
+                    _ => 0.0,
+                };
+                (sum + dc) as f32
+            })
+            .collect::<Vec<f32>>();
+        // gibbs ringing overshoots the corners; keep the peaks in range so they don't clip
+        let peak = table.iter().fold(0.0f32, |p, x| p.max(x.abs()));
+        if peak > 1.0 {
+            table.iter_mut().for_each(|x| *x /= peak);
+        }
+        table
+    }
+
+    fn lookup(&self, t: f64) -> f64 {
+        let pos = t * TABLE_LEN as f64;
+        let i = pos as usize % TABLE_LEN;
+        let frac = pos.fract();
+        let (a, b) = (self.table[i] as f64, self.table[(i + 1) % TABLE_LEN] as f64);
+        a + (b - a) * frac
+    }
+}
+
+impl Generator for Oscillator {
+    fn next_sample(&mut self) -> f32 {
+        let t = self.phase;
+        let v = match self.band_limit {
+            BandLimit::None => self.naive(t),
+            _ if self.waveform == Waveform::Sine => self.naive(t),
+            BandLimit::PolyBlep => self.poly_blep(t),
+            BandLimit::Additive => self.lookup(t),
+        };
+        self.phase = (self.phase + self.dt).fract();
+        v as f32
+    }
+}
+
+// polynomial approximation to the band-limited step residual, for a step of +2
+// at t = 0, spread over the samples either side
+fn blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+// the integral of blep: the residual for a change in slope of 1 per sample at t = 0
+fn blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = 1.0 - t / dt;
+        x * x * x / 6.0
+    } else if t > 1.0 - dt {
+        let x = 1.0 + (t - 1.0) / dt;
+        x * x * x / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    /// uniformly distributed white noise
+    White,
+    /// normally distributed white noise
+    Gaussian,
+    /// -3 dB/octave
+    Pink,
+    /// -6 dB/octave
+    Brown,
+    /// gaussian noise through 4th order butterworth high and low pass filters
+    Band,
+}
+
+impl NoiseKind {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "white" => Ok(NoiseKind::White),
+            "gaussian" => Ok(NoiseKind::Gaussian),
+            "pink" => Ok(NoiseKind::Pink),
+            "brown" => Ok(NoiseKind::Brown),
+            "band" => Ok(NoiseKind::Band),
+            _ => Err(anyhow!("unknown noise {}", name)),
+        }
+    }
+}
+
+// splitmix64: small, fast and the same everywhere, so a seed always gives the same noise
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // in [0, 1)
+    pub(crate) fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // box-muller
+    fn gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+type Filter = Box<dyn FnMut(f64) -> f64 + Send>;
+
+fn noise_filter(kind: NoiseKind, band: (f32, f32), sample_rate: f32) -> Filter {
+    let sr = sample_rate as f64;
+    match kind {
+        NoiseKind::White | NoiseKind::Gaussian => Box::new(|x| x),
+        // Paul Kellet's refined pink filter: parallel one-pole sections, good to
+        // about 0.05 dB from 10 Hz up
+        NoiseKind::Pink => {
+            let mut b = [0.0f64; 7];
+            Box::new(move |x| {
+                b[0] = 0.99886 * b[0] + x * 0.055_517_9;
+                b[1] = 0.99332 * b[1] + x * 0.075_075_9;
+                b[2] = 0.96900 * b[2] + x * 0.153_852;
+                b[3] = 0.86650 * b[3] + x * 0.310_485_6;
+                b[4] = 0.55000 * b[4] + x * 0.532_952_2;
+                b[5] = -0.7616 * b[5] - x * 0.016_898;
+                let y = b.iter().sum::<f64>() + x * 0.5362;
+                b[6] = x * 0.115_926;
+                y
+            })
+        },
+        // an integrator that leaks below 10 Hz, so it doesn't wander off
+        NoiseKind::Brown => {
+            let a = 1.0 - 2.0 * PI * 10.0 / sr;
+            let mut y = 0.0;
+            Box::new(move |x| {
+                y = a * y + x;
+                y
+            })
+        },
+        NoiseKind::Band => {
+            let (lo, hi) = (band.0 as f64, band.1 as f64);
+            let mut sections = Vec::new();
+            if lo > 0.0 {
+                sections.extend(BUTTERWORTH_4.iter().map(|q| Biquad::high_pass(lo, *q, sr)));
+            }
+            if hi < sr / 2.0 {
+                sections.extend(BUTTERWORTH_4.iter().map(|q| Biquad::low_pass(hi, *q, sr)));
+            }
+            Box::new(move |x| sections.iter_mut().fold(x, |x, s| s.process(x)))
+        },
+    }
+}
+
+/// Noise with an RMS level of 1, the same every time for a given seed.
+pub struct Noise {
+    rng: Rng,
+    uniform: bool,
+    filter: Filter,
+    gain: f64,
+}
+
+impl Noise {
+    /// `band` is the (low, high) cutoff in Hz for band limited noise.
+    pub fn new(kind: NoiseKind, band: (f32, f32), sample_rate: f32, seed: u64) -> Self {
+        // unit variance noise through the filter comes out with a variance equal
+        // to the energy of the filter's impulse response
+        let mut probe = noise_filter(kind, band, sample_rate);
+        let energy: f64 = (0..1 << 18)
+            .map(|i| probe(if i == 0 { 1.0 } else { 0.0 }).powi(2))
+            .sum();
+        Self {
+            rng: Rng(seed),
+            uniform: kind == NoiseKind::White,
+            filter: noise_filter(kind, band, sample_rate),
+            gain: 1.0 / energy.sqrt(),
+        }
+    }
+}
+
+impl Generator for Noise {
+    fn next_sample(&mut self) -> f32 {
+        let x = if self.uniform {
+            // uniform on +/- sqrt(3) has unit variance
+            3f64.sqrt() * (2.0 * self.rng.uniform() - 1.0)
+        } else {
+            self.rng.gaussian()
+        };
+        ((self.filter)(x) * self.gain) as f32
+    }
+}
@@ -1 +1,14 @@
 pub mod scope;
+pub mod spectrum;
+pub mod analysis;
+pub mod sweep;
+pub mod correlation;
+pub mod script;
+pub mod report;
+pub mod audiofile;
+pub mod record;
+pub mod filter;
+pub mod generator;
+pub mod loudness;
+pub mod multitone;
+pub mod weighting;
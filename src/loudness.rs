@@ -0,0 +1,310 @@
+// Loudness and true peak metering after ITU-R BS.1770-4 and EBU R128 / Tech 3341
+// and 3342: K-weighted momentary (400 ms), short-term (3 s) and gated integrated
+// loudness, loudness range, and true peak from 4x oversampling.
+
+use crate::filter::Biquad;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// loudness blocks are built from 100 ms pieces: 4 make a momentary block, 30 a short-term one
+const PIECES_MOMENTARY: usize = 4;
+const PIECES_SHORT_TERM: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+// below the ungated loudness, in LU
+const RELATIVE_GATE: f64 = -10.0;
+const LRA_RELATIVE_GATE: f64 = -20.0;
+
+// gated loudness is worked out from histograms of block loudness, as libebur128
+// does, so the cost doesn't grow with the length of the programme. Bins span
+// from the absolute gate up to this, in LUFS.
+const HISTOGRAM_TOP: f64 = 5.0;
+const HISTOGRAM_BINS: usize = 1000;
+
+// true peak interpolator: 4 phases of a windowed sinc
+const OVERSAMPLE: usize = 4;
+const TAPS_PER_PHASE: usize = 16;
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// The two stage K-weighting filter, a high shelf modelling the head followed by
+/// the RLB high pass, worked out for any sample rate.
+pub fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // shelf: about +4 dB above 1.5 kHz
+    let (f0, gain_db, q) = (1_681.974_450_955_533, 3.999_843_853_973_347, 0.707_175_236_955_419_6);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    // high pass at about 38 Hz
+    let (f0, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+    [shelf, high_pass]
+}
+
+// 4x interpolation, one FIR per output phase
+struct TruePeak {
+    phases: Vec<[f64; TAPS_PER_PHASE]>,
+    history: VecDeque<f64>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        // centred on a tap of phase 0, whose other taps fall on the zeros of the sinc,
+        // so it passes the input samples through and the true peak is never below
+        // the sample peak. the last tap of the odd length prototype is a zero of the window.
+        let len = OVERSAMPLE * TAPS_PER_PHASE;
+        let center = len / 2;
+        let h: Vec<f64> = (0..len)
+            .map(|i| {
+                let x = (i as f64 - center as f64) / OVERSAMPLE as f64;
+                let sinc = match (i.abs_diff(center) % OVERSAMPLE, i == center) {
+                    (_, true) => 1.0,
+                    // exactly, where sin() would leave rounding error
+                    (0, false) => 0.0,
+                    _ => (PI * x).sin() / (PI * x),
+                };
+                // blackman window
+                let w = 0.42 - 0.5 * (PI * i as f64 / center as f64).cos()
+                    + 0.08 * (2.0 * PI * i as f64 / center as f64).cos();
+                sinc * w
+            })
+            .collect();
+        let phases = (0..OVERSAMPLE)
+            .map(|p| {
+                let mut taps = [0.0; TAPS_PER_PHASE];
+                for (t, tap) in taps.iter_mut().enumerate() {
+                    *tap = h[t * OVERSAMPLE + p];
+                }
+                // each phase passes dc at unity
+                let sum: f64 = taps.iter().sum();
+                taps.map(|t| t / sum)
+            })
+            .collect();
+        Self { phases, history: VecDeque::from(vec![0.0; TAPS_PER_PHASE]), peak: 0.0 }
+    }
+
+    fn push(&mut self, x: f64) {
+        self.history.pop_back();
+        self.history.push_front(x);
+        for taps in &self.phases {
+            let y: f64 = taps.iter().zip(&self.history).map(|(t, x)| t * x).sum();
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+}
+
+// Counts and summed powers of blocks by loudness, for those above the absolute gate.
+struct Histogram {
+    counts: Vec<u64>,
+    power: Vec<f64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self { counts: vec![0; HISTOGRAM_BINS], power: vec![0.0; HISTOGRAM_BINS] }
+    }
+
+    // the bin holding `lufs`; louder than the top goes in the last
+    fn bin(lufs: f64) -> usize {
+        let at = (lufs - ABSOLUTE_GATE) / (HISTOGRAM_TOP - ABSOLUTE_GATE) * HISTOGRAM_BINS as f64;
+        (at.max(0.0) as usize).min(HISTOGRAM_BINS - 1)
+    }
+
+    fn center(bin: usize) -> f64 {
+        ABSOLUTE_GATE + (bin as f64 + 0.5) * (HISTOGRAM_TOP - ABSOLUTE_GATE) / HISTOGRAM_BINS as f64
+    }
+
+    fn push(&mut self, power: f64) {
+        let lufs = to_lufs(power);
+        if lufs > ABSOLUTE_GATE {
+            let bin = Self::bin(lufs);
+            self.counts[bin] += 1;
+            self.power[bin] += power;
+        }
+    }
+
+    // the first bin above `relative` LU from the mean power of all the blocks
+    fn gate(&self, relative: f64) -> Option<usize> {
+        let count: u64 = self.counts.iter().sum();
+        if count == 0 {
+            return None;
+        }
+        let mean = self.power.iter().sum::<f64>() / count as f64;
+        Some(Self::bin(to_lufs(mean) + relative))
+    }
+}
+
+/// A loudness meter for a group of channels measured together.
+pub struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    // BS.1770 channel weights
+    weights: Vec<f64>,
+    true_peaks: Vec<TruePeak>,
+    piece_len: usize,
+    // weighted mean square of the piece being filled, and how many frames are in it
+    piece_sum: f64,
+    piece_frames: usize,
+    // the last PIECES_SHORT_TERM pieces
+    pieces: VecDeque<f64>,
+    // every momentary block, for integrated loudness, and every short-term one, for LRA
+    momentary_blocks: Histogram,
+    short_term_blocks: Histogram,
+}
+
+impl LoudnessMeter {
+    /// Six channels are taken to be L R C LFE Ls Rs: the LFE isn't counted and
+    /// the surrounds count 1.5 dB up. Any other count is weighted evenly.
+    pub fn new(channels: usize, sample_rate: f32) -> Self {
+        let weights = if channels == 6 {
+            vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+        } else {
+            vec![1.0; channels]
+        };
+        Self {
+            filters: (0..channels).map(|_| k_weighting(sample_rate as f64)).collect(),
+            weights,
+            true_peaks: (0..channels).map(|_| TruePeak::new()).collect(),
+            piece_len: (sample_rate / 10.0).round() as usize,
+            piece_sum: 0.0,
+            piece_frames: 0,
+            pieces: VecDeque::new(),
+            momentary_blocks: Histogram::new(),
+            short_term_blocks: Histogram::new(),
+        }
+    }
+
+    /// Add a frame, one sample per channel.
+    pub fn push(&mut self, frame: &[f32]) {
+        for (i, s) in frame.iter().enumerate().take(self.filters.len()) {
+            let x = *s as f64;
+            self.true_peaks[i].push(x);
+            let [shelf, high_pass] = &mut self.filters[i];
+            let y = high_pass.process(shelf.process(x));
+            self.piece_sum += self.weights[i] * y * y;
+        }
+        self.piece_frames += 1;
+        if self.piece_frames == self.piece_len {
+            self.pieces.push_back(self.piece_sum / self.piece_len as f64);
+            if self.pieces.len() > PIECES_SHORT_TERM {
+                self.pieces.pop_front();
+            }
+            self.piece_sum = 0.0;
+            self.piece_frames = 0;
+            if let Some(m) = self.mean_of_last(PIECES_MOMENTARY) {
+                self.momentary_blocks.push(m);
+            }
+            if let Some(s) = self.mean_of_last(PIECES_SHORT_TERM) {
+                self.short_term_blocks.push(s);
+            }
+        }
+    }
+
+    // mean power of the last `n` pieces, once there are that many
+    fn mean_of_last(&self, n: usize) -> Option<f64> {
+        (self.pieces.len() >= n).then(|| self.pieces.iter().rev().take(n).sum::<f64>() / n as f64)
+    }
+
+    /// Momentary loudness, LUFS, over the last 400 ms.
+    pub fn momentary(&self) -> Option<f32> {
+        self.mean_of_last(PIECES_MOMENTARY).map(|p| to_lufs(p) as f32)
+    }
+
+    /// Short-term loudness, LUFS, over the last 3 s.
+    pub fn short_term(&self) -> Option<f32> {
+        self.mean_of_last(PIECES_SHORT_TERM).map(|p| to_lufs(p) as f32)
+    }
+
+    /// Gated loudness, LUFS, of everything so far.
+    pub fn integrated(&self) -> Option<f32> {
+        let h = &self.momentary_blocks;
+        let gate = h.gate(RELATIVE_GATE)?;
+        let count: u64 = h.counts[gate..].iter().sum();
+        (count > 0).then(|| to_lufs(h.power[gate..].iter().sum::<f64>() / count as f64) as f32)
+    }
+
+    /// Loudness range, LU: the spread from the 10th to the 95th percentile of the
+    /// gated short-term loudness.
+    pub fn range(&self) -> Option<f32> {
+        let h = &self.short_term_blocks;
+        let gate = h.gate(LRA_RELATIVE_GATE)?;
+        let count: u64 = h.counts[gate..].iter().sum();
+        if count == 0 {
+            return None;
+        }
+        // the loudness of the nth gated block, counting from the quietest
+        let nth = |n: u64| {
+            let mut seen = 0;
+            for (bin, c) in h.counts.iter().enumerate().skip(gate) {
+                seen += c;
+                if seen > n {
+                    return Histogram::center(bin);
+                }
+            }
+            Histogram::center(HISTOGRAM_BINS - 1)
+        };
+        let percentile = |p: f64| nth(((count - 1) as f64 * p).round() as u64);
+        Some((percentile(0.95) - percentile(0.10)) as f32)
+    }
+
+    /// Highest true peak of any channel so far, as a linear amplitude.
+    pub fn true_peak(&self) -> f32 {
+        self.true_peaks.iter().fold(0.0, |peak, t| t.peak.max(peak)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // true peak of `samples` on a single channel
+    fn true_peak(samples: impl Iterator<Item = f32>) -> f32 {
+        let mut meter = LoudnessMeter::new(1, 48000.0);
+        for s in samples {
+            meter.push(&[s]);
+        }
+        meter.true_peak()
+    }
+
+    #[test]
+    fn tech_3341_case_1() {
+        // a stereo 1 kHz sine at -23 dBFS reads -23 LUFS however it's measured
+        let fs = 48000.0;
+        let mut meter = LoudnessMeter::new(2, fs);
+        let ampl = 10f64.powf(-23.0 / 20.0);
+        for i in 0..20 * fs as usize {
+            let s = (ampl * (2.0 * PI * 1000.0 * i as f64 / fs as f64).sin()) as f32;
+            meter.push(&[s, s]);
+        }
+        for (what, lufs) in [("M", meter.momentary()), ("S", meter.short_term()), ("I", meter.integrated())] {
+            let lufs = lufs.unwrap();
+            assert!((lufs + 23.0).abs() <= 0.1, "{what} {lufs:.2} LUFS, expected -23");
+        }
+    }
+
+    #[test]
+    fn true_peak_is_never_below_the_sample_peak() {
+        let impulse = (0..4800).map(|i| if i == 100 { 1.0 } else { 0.0 });
+        assert!(true_peak(impulse) >= 1.0);
+        assert!(true_peak(std::iter::repeat_n(1.0, 4800)) >= 1.0);
+        let sine = (0..48000).map(|i| (2.0 * PI * 50.0 * i as f64 / 48000.0).cos() as f32);
+        assert!(true_peak(sine) >= 1.0);
+    }
+
+    #[test]
+    fn true_peak_finds_the_peak_between_samples() {
+        // a quarter of the sample rate, 45 degrees out, samples at +/-0.707 of a full scale peak
+        let sine = (0..48000).map(|i| (PI / 2.0 * i as f64 + PI / 4.0).sin() as f32);
+        let db = 20.0 * true_peak(sine).log10();
+        assert!(db.abs() < 0.5, "{db:.2} dBTP, expected 0");
+    }
+}
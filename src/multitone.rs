@@ -0,0 +1,239 @@
+// Multitone measurement: a periodic stimulus with every tone centred on a bin of
+// an FFT one period long, so a rectangular window sees each tone in exactly one
+// bin. Response, distortion and noise all come out of a few periods of capture.
+
+use anyhow::{anyhow, Result};
+use rustfft::{FftPlanner, num_complex::Complex};
+use crate::generator::Rng;
+use crate::spectrum;
+use std::f64::consts::PI;
+
+// random phase sets tried, keeping the one with the lowest crest factor
+const RANDOM_TRIES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Phases {
+    /// M. R. Schroeder, "Synthesis of low-peak-factor signals and binary sequences
+    /// with low autocorrelation" (1970)
+    #[default]
+    Schroeder,
+    Random,
+}
+
+impl Phases {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "schroeder" => Ok(Phases::Schroeder),
+            "random" => Ok(Phases::Random),
+            _ => Err(anyhow!("unknown phases {}", name)),
+        }
+    }
+}
+
+/// Frequencies from `lo` to `hi`, `per_octave` to an octave.
+pub fn log_spaced(lo: f32, hi: f32, per_octave: f32) -> Vec<f32> {
+    (0..)
+        .map(|i| lo * 2f32.powf(i as f32 / per_octave))
+        .take_while(|f| *f <= hi)
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct Multitone {
+    /// period, and the size of the FFT used to analyse it
+    pub n: usize,
+    pub sample_rate: f32,
+    /// FFT bin of each tone, ascending
+    pub bins: Vec<usize>,
+    /// phase of each tone, radians
+    pub phases: Vec<f64>,
+}
+
+impl Multitone {
+    /// Tones as near as possible to `freqs`. Frequencies that round to the same
+    /// bin become one tone; ones at dc or nyquist and above are dropped.
+    pub fn new(freqs: &[f32], n: usize, sample_rate: f32, phases: Phases, seed: u64) -> Result<Self> {
+        let mut bins: Vec<usize> = freqs.iter()
+            .map(|f| (*f as f64 * n as f64 / sample_rate as f64).round() as usize)
+            .filter(|b| *b > 0 && *b < n / 2)
+            .collect();
+        bins.sort();
+        bins.dedup();
+        if bins.is_empty() {
+            return Err(anyhow!("no tones between dc and nyquist"));
+        }
+
+        let k = bins.len() as f64;
+        let mut mt = Self { n, sample_rate, bins, phases: Vec::new() };
+        match phases {
+            // with equal powers the general formula reduces to -pi i (i - 1) / k, for tones
+            // i counted from 1
+            Phases::Schroeder => {
+                mt.phases = (0..mt.bins.len()).map(|i| -PI * ((i + 1) * i) as f64 / k).collect();
+            },
+            Phases::Random => {
+                let mut rng = Rng(seed);
+                let mut best = f32::MAX;
+                for _ in 0..RANDOM_TRIES {
+                    let candidate = Self {
+                        phases: mt.bins.iter().map(|_| 2.0 * PI * rng.uniform()).collect(),
+                        ..mt.clone()
+                    };
+                    let crest = candidate.crest_factor_db();
+                    if crest < best {
+                        best = crest;
+                        mt.phases = candidate.phases;
+                    }
+                }
+            },
+        }
+        Ok(mt)
+    }
+
+    pub fn freqs(&self) -> Vec<f32> {
+        self.bins.iter().map(|b| self.bin_hz(*b)).collect()
+    }
+
+    fn bin_hz(&self, bin: usize) -> f32 {
+        bin as f32 * self.sample_rate / self.n as f32
+    }
+
+    // one period of the sum of unit cosines
+    fn unscaled(&self) -> Vec<f64> {
+        let mut buf = vec![Complex::new(0.0, 0.0); self.n];
+        for (b, phase) in self.bins.iter().zip(&self.phases) {
+            buf[*b] = Complex::from_polar(1.0, *phase);
+        }
+        FftPlanner::<f64>::new().plan_fft_inverse(self.n).process(&mut buf);
+        buf.iter().map(|c| c.re).collect()
+    }
+
+    // peak of the sum of unit cosines
+    fn peak(&self) -> f64 {
+        self.unscaled().iter().fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    /// Amplitude of each tone in `samples`.
+    pub fn tone_ampl(&self) -> f32 {
+        (1.0 / self.peak()) as f32
+    }
+
+    /// One period, peaking at full scale.
+    pub fn samples(&self) -> Vec<f32> {
+        let x = self.unscaled();
+        let peak = x.iter().fold(0.0, |peak: f64, s| s.abs().max(peak));
+        x.iter().map(|s| (s / peak) as f32).collect()
+    }
+
+    pub fn crest_factor_db(&self) -> f32 {
+        // each unit cosine has a mean square of 1/2
+        let rms = (self.bins.len() as f64 / 2.0).sqrt();
+        spectrum::to_db((self.peak() / rms) as f32)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tone {
+    pub freq: f32,
+    /// dBFS
+    pub level_db: f32,
+    /// level relative to the stimulus
+    pub gain_db: f32,
+    /// phase relative to the stimulus, degrees. Includes the delay through the system.
+    pub phase_deg: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bin {
+    pub freq: f32,
+    pub tone: bool,
+    /// level of the average over periods, with the noise left in it removed, dBFS
+    pub level_db: f32,
+    /// noise in one period, dBFS
+    pub noise_db: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct MultitoneResult {
+    pub tones: Vec<Tone>,
+    pub bins: Vec<Bin>,
+    /// everything in the bins between the tones that repeats every period, relative to the tones
+    pub distortion_db: f32,
+    /// everything that doesn't, relative to the tones
+    pub noise_db: f32,
+}
+
+/// Analyse a capture of whole periods of `mt` played at `ampl`. Noise is told
+/// from distortion by how much each bin varies from period to period, so at
+/// least two periods are needed.
+pub fn analyze(mt: &Multitone, ampl: f32, recording: &[f32]) -> Result<MultitoneResult> {
+    let n = mt.n;
+    let periods = recording.len() / n;
+    if periods < 2 {
+        return Err(anyhow!("need at least two periods to analyse, got {}", periods));
+    }
+
+    // spectrum of each period, scaled to peak amplitude
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(n);
+    let spectra: Vec<Vec<Complex<f64>>> = recording.chunks_exact(n)
+        .map(|period| {
+            let mut buf: Vec<Complex<f64>> = period.iter().map(|s| Complex::new(*s as f64, 0.0)).collect();
+            fft.process(&mut buf);
+            buf[..=n / 2].iter().map(|c| c * 2.0 / n as f64).collect()
+        })
+        .collect();
+
+    let p = periods as f64;
+    let mut tones = Vec::new();
+    let mut bins = Vec::new();
+    let (mut tone_power, mut distortion_power, mut noise_power) = (0.0, 0.0, 0.0);
+    let stimulus = (ampl * mt.tone_ampl()) as f64;
+    let db = |power: f64| spectrum::to_db(power.sqrt() as f32);
+    // skip dc and nyquist
+    for k in 1..n / 2 {
+        let mean = spectra.iter().map(|s| s[k]).sum::<Complex<f64>>() / p;
+        // variance of one period, and what of it is left in the mean
+        let variance = spectra.iter().map(|s| (s[k] - mean).norm_sqr()).sum::<f64>() / (p - 1.0);
+        let coherent = (mean.norm_sqr() - variance / p).max(0.0);
+        noise_power += variance;
+
+        let tone = mt.bins.binary_search(&k);
+        if let Ok(i) = tone {
+            tone_power += coherent;
+            let phase = (mean.arg() - mt.phases[i]).to_degrees();
+            tones.push(Tone {
+                freq: mt.bin_hz(k),
+                level_db: db(coherent),
+                gain_db: db(coherent / (stimulus * stimulus)),
+                // wrapped to +/-180
+                phase_deg: (phase - 360.0 * (phase / 360.0).round()) as f32,
+            });
+        } else {
+            distortion_power += coherent;
+        }
+        bins.push(Bin { freq: mt.bin_hz(k), tone: tone.is_ok(), level_db: db(coherent), noise_db: db(variance) });
+    }
+
+    Ok(MultitoneResult {
+        tones,
+        bins,
+        distortion_db: db(distortion_power / tone_power),
+        noise_db: db(noise_power / tone_power),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schroeder_phases_keep_the_crest_factor_low() {
+        // 100 tones on consecutive bins; in phase they would peak 23 dB over their rms
+        let fs = 48000.0;
+        let freqs: Vec<f32> = (1..=100).map(|k| k as f32 * fs / 4096.0).collect();
+        let mt = Multitone::new(&freqs, 4096, fs, Phases::Schroeder, 0).unwrap();
+        assert_eq!(mt.bins.len(), 100);
+        let crest = mt.crest_factor_db();
+        assert!(crest < 5.0, "crest factor {crest:.2} dB");
+    }
+}
@@ -0,0 +1,78 @@
+// Writing captured audio to WAV files.
+
+use anyhow::{anyhow, Result};
+use crate::audiofile::PcmFormat;
+use std::{fs::File, io::BufWriter};
+
+type Writer = hound::WavWriter<BufWriter<File>>;
+
+/// Writes frames of captured channels to one multichannel WAV file, or to
+/// one mono file per channel.
+pub struct WavRecorder {
+    writers: Vec<Writer>,
+    paths: Vec<String>,
+    format: PcmFormat,
+    frames: u64,
+}
+
+impl WavRecorder {
+    /// `channels` are the device channel numbers being recorded, used to name
+    /// the files when `split` is set: out.wav becomes out_ch0.wav, out_ch1.wav, ...
+    pub fn create(path: &str, channels: &[u8], sample_rate: u32, format: PcmFormat, split: bool) -> Result<Self> {
+        let (bits_per_sample, sample_format) = match format {
+            PcmFormat::S16 => (16, hound::SampleFormat::Int),
+            PcmFormat::S24 => (24, hound::SampleFormat::Int),
+            PcmFormat::S32 => (32, hound::SampleFormat::Int),
+            PcmFormat::F32 => (32, hound::SampleFormat::Float),
+        };
+        let spec = |channels: usize| hound::WavSpec { channels: channels as u16, sample_rate, bits_per_sample, sample_format };
+
+        let paths: Vec<String> = if split {
+            let stem = path.strip_suffix(".wav").unwrap_or(path);
+            channels.iter().map(|ch| format!("{stem}_ch{ch}.wav")).collect()
+        } else {
+            vec![path.to_string()]
+        };
+        let writers = paths.iter()
+            .map(|p| {
+                let spec = spec(if split { 1 } else { channels.len() });
+                hound::WavWriter::create(p, spec).map_err(|e| anyhow!("{}: {}", p, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { writers, paths, format, frames: 0 })
+    }
+
+    /// Write one frame, one sample per recorded channel.
+    pub fn write(&mut self, frame: &[f32]) -> Result<()> {
+        let single = self.writers.len() == 1;
+        for (i, s) in frame.iter().enumerate() {
+            let writer = &mut self.writers[if single { 0 } else { i }];
+            let s = s.clamp(-1.0, 1.0);
+            match self.format {
+                PcmFormat::S16 => writer.write_sample((s * i16::MAX as f32).round() as i16)?,
+                PcmFormat::S24 => writer.write_sample((s * 8_388_607.0).round() as i32)?,
+                PcmFormat::S32 => writer.write_sample((s as f64 * i32::MAX as f64).round() as i32)?,
+                // float files keep anything over full scale
+                PcmFormat::F32 => writer.write_sample(frame[i])?,
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Fill in the headers and close the files.
+    pub fn finalize(self) -> Result<()> {
+        for w in self.writers {
+            w.finalize()?;
+        }
+        Ok(())
+    }
+}
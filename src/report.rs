@@ -0,0 +1,261 @@
+// Measurement results collected for CI, written as JSON, CSV or JUnit XML.
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Junit,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "junit" | "xml" => Ok(Format::Junit),
+            _ => Err(anyhow!("unknown report format {}", name)),
+        }
+    }
+
+    /// Guess the format from a file name's extension.
+    pub fn from_path(path: &str) -> Result<Self> {
+        let ext = std::path::Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        Self::from_name(ext).map_err(|_| anyhow!("can't tell the report format of {}", path))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// RFC 3339, UTC
+    pub timestamp: String,
+    pub ch: u8,
+    pub metric: String,
+    pub value: f32,
+    pub unit: String,
+    /// name given to a check, if any
+    pub name: Option<String>,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    /// None for plain measurements, Some(pass) for checks against limits
+    pub pass: Option<bool>,
+}
+
+impl Record {
+    /// A measurement taken now, with no limits.
+    pub fn new(ch: u8, metric: &str, value: f32, unit: &str) -> Self {
+        Self {
+            timestamp: now(),
+            ch,
+            metric: metric.to_string(),
+            value,
+            unit: unit.to_string(),
+            name: None,
+            min: None,
+            max: None,
+            pass: None,
+        }
+    }
+
+    pub fn verdict(&self) -> &'static str {
+        match self.pass {
+            None => "",
+            Some(true) => "pass",
+            Some(false) => "fail",
+        }
+    }
+
+    // name used for the test case in junit output
+    fn test_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ch{} {}", name, self.ch, self.metric),
+            None => format!("ch{} {}", self.ch, self.metric),
+        }
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+pub struct Report {
+    pub device: String,
+    /// description of the stream configuration
+    pub config: String,
+    pub started: String,
+    pub records: Vec<Record>,
+}
+
+impl Report {
+    pub fn new(device: &str, config: &str) -> Self {
+        Self { device: device.to_string(), config: config.to_string(), started: now(), records: Vec::new() }
+    }
+
+    pub fn push(&mut self, record: Record) {
+        self.records.push(record);
+    }
+
+    /// Fold a reading from a continuous measurement into three records per
+    /// channel and metric, holding the last, lowest and highest values, so
+    /// the report doesn't grow for as long as the measurement runs.
+    pub fn update(&mut self, ch: u8, metric: &str, value: f32, unit: &str) {
+        let last = |_: f32, new: f32| new;
+        let summary = [
+            (metric.to_string(), last as fn(f32, f32) -> f32),
+            (format!("{metric} min"), f32::min),
+            (format!("{metric} max"), f32::max),
+        ];
+        for (metric, keep) in summary {
+            let found = self.records.iter_mut().find(|r| r.ch == ch && r.metric == metric && r.pass.is_none());
+            match found {
+                Some(r) => {
+                    r.value = keep(r.value, value);
+                    r.timestamp = now();
+                },
+                None => self.records.push(Record::new(ch, &metric, value, unit)),
+            }
+        }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.records.iter().filter(|r| r.pass == Some(false)).count()
+    }
+
+    pub fn to_json(&self) -> String {
+        let records: Vec<_> = self.records.iter()
+            .map(|r| json!({
+                "timestamp": r.timestamp,
+                "ch": r.ch,
+                "metric": r.metric,
+                "value": json_f32(r.value),
+                "unit": r.unit,
+                "name": r.name,
+                "min": r.min.map(json_f32),
+                "max": r.max.map(json_f32),
+                "verdict": r.pass.map(|_| r.verdict()),
+            }))
+            .collect();
+        let report = json!({
+            "device": self.device,
+            "config": self.config,
+            "started": self.started,
+            "records": records,
+        });
+        serde_json::to_string_pretty(&report).unwrap()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let opt = |x: Option<f32>| x.map(|x| x.to_string()).unwrap_or_default();
+        let mut out = String::from("timestamp,device,config,ch,metric,value,unit,name,min,max,verdict\n");
+        for r in &self.records {
+            let fields = [
+                r.timestamp.clone(),
+                self.device.clone(),
+                self.config.clone(),
+                r.ch.to_string(),
+                r.metric.clone(),
+                r.value.to_string(),
+                r.unit.clone(),
+                r.name.clone().unwrap_or_default(),
+                opt(r.min),
+                opt(r.max),
+                r.verdict().to_string(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            out += &fields.join(",");
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_junit(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out += &format!("<testsuites tests=\"{}\" failures=\"{}\">\n", self.records.len(), self.failures());
+        out += &format!("  <testsuite name=\"autt\" tests=\"{}\" failures=\"{}\" errors=\"0\" timestamp=\"{}\">\n",
+            self.records.len(), self.failures(), xml_escape(&self.started));
+        out += "    <properties>\n";
+        out += &format!("      <property name=\"device\" value=\"{}\"/>\n", xml_escape(&self.device));
+        out += &format!("      <property name=\"config\" value=\"{}\"/>\n", xml_escape(&self.config));
+        out += "    </properties>\n";
+        for r in &self.records {
+            let result = format!("{} {} {}", r.value, r.unit, limits(r.min, r.max));
+            out += &format!("    <testcase classname=\"autt.{}\" name=\"{}\" time=\"0\">\n",
+                xml_escape(&r.metric), xml_escape(&r.test_name()));
+            if r.pass == Some(false) {
+                out += &format!("      <failure message=\"{}\" type=\"limit\"/>\n", xml_escape(result.trim()));
+            }
+            out += &format!("      <system-out>{} {}</system-out>\n", xml_escape(&r.timestamp), xml_escape(result.trim()));
+            out += "    </testcase>\n";
+        }
+        out += "  </testsuite>\n</testsuites>\n";
+        out
+    }
+
+    pub fn write(&self, path: &str, format: Format) -> Result<()> {
+        let text = match format {
+            Format::Json => self.to_json(),
+            Format::Csv => self.to_csv(),
+            Format::Junit => self.to_junit(),
+        };
+        std::fs::write(path, text).map_err(|e| anyhow!("{}: {}", path, e))
+    }
+}
+
+fn limits(min: Option<f32>, max: Option<f32>) -> String {
+    match (min, max) {
+        (Some(min), Some(max)) => format!("(limits {} to {})", min, max),
+        (Some(min), None) => format!("(min {})", min),
+        (None, Some(max)) => format!("(max {})", max),
+        (None, None) => String::new(),
+    }
+}
+
+// the shortest decimal that reads back as the same f32, rather than the widened f64.
+// NaN and infinities aren't valid json numbers and come out as null.
+fn json_f32(x: f32) -> serde_json::Value {
+    json!(x.to_string().parse::<f64>().ok().filter(|x| x.is_finite()))
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_numbers_read_back_as_written() {
+        assert_eq!(json_f32(0.1).to_string(), "0.1");
+        assert_eq!(json_f32(-23.0).to_string(), "-23.0");
+        assert_eq!(json_f32(f32::NAN), serde_json::Value::Null);
+        assert_eq!(json_f32(f32::INFINITY), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("thd"), "thd");
+        assert_eq!(csv_field("gain 1kHz, left"), "\"gain 1kHz, left\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn xml_is_escaped() {
+        assert_eq!(xml_escape("a < b && \"c\" > d"), "a &lt; b &amp;&amp; &quot;c&quot; &gt; d");
+        assert_eq!(xml_escape("&lt;"), "&amp;lt;");
+    }
+}
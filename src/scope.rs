@@ -1,11 +1,260 @@
 use eframe::egui;
 use egui::{Label, RichText};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use egui_plotter::EguiBackend;
 use plotters::prelude::*;
 use egui_taffy::{taffy, tui, TuiBuilderLogic, TuiBuilder, TuiWidget};
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+use crate::spectrum::{self, Window};
+use crate::correlation;
 //use taffy;
 
+// bottom of the spectrum plot's dBFS scale
+const FFT_FLOOR_DB: f32 = -140.0;
+
+/// grid of the scope traces
+pub const DIVISIONS_X: usize = 10;
+pub const DIVISIONS_Y: usize = 8;
+
+// timebase limits, seconds per division
+pub const MIN_TIMEBASE: f32 = 1e-5;
+pub const MAX_TIMEBASE: f32 = 0.5;
+
+/// The next value up or down a 1, 2, 5 sequence, like a scope's knobs.
+pub fn step_125(x: f32, up: bool) -> f32 {
+    // allow for x being a hair under a step
+    let decade = 10f32.powf((x * 1.001).log10().floor());
+    let m = x / decade;
+    let steps = [0.5, 1.0, 2.0, 5.0, 10.0];
+    let next = if up {
+        steps.iter().find(|s| **s > m * 1.001)
+    } else {
+        steps.iter().rev().find(|s| **s < m * 0.999)
+    };
+    next.copied().unwrap_or(m) * decade
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Coupling {
+    #[default]
+    Dc,
+    /// the mean of the capture is taken off
+    Ac,
+}
+
+impl Coupling {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "dc" => Ok(Coupling::Dc),
+            "ac" => Ok(Coupling::Ac),
+            _ => Err(anyhow!("unknown coupling {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Coupling::Dc => "DC",
+            Coupling::Ac => "AC",
+        }
+    }
+}
+
+/// How a channel's trace is scaled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vertical {
+    /// full scale per division
+    Linear(f32),
+    /// dB per division, down from 0 dBFS
+    Db(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub vertical: Vertical,
+    /// divisions the trace is moved up
+    pub offset: f32,
+    pub coupling: Coupling,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self { vertical: Vertical::Linear(0.25), offset: 0.0, coupling: Coupling::Dc }
+    }
+}
+
+impl View {
+    // bottom and top of the plot
+    fn range(&self) -> (f32, f32) {
+        match self.vertical {
+            Vertical::Linear(per_div) => {
+                let half = per_div * DIVISIONS_Y as f32 / 2.0;
+                (-half - self.offset * per_div, half - self.offset * per_div)
+            },
+            Vertical::Db(per_div) => {
+                let top = -self.offset * per_div;
+                (top - per_div * DIVISIONS_Y as f32, top)
+            },
+        }
+    }
+
+    // where a sample goes on the plot
+    fn plot(&self, s: f32, dc: f32) -> f32 {
+        let s = match self.coupling {
+            Coupling::Dc => s,
+            Coupling::Ac => s - dc,
+        };
+        match self.vertical {
+            Vertical::Linear(_) => s,
+            Vertical::Db(_) => spectrum::to_db(s.abs()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Vulcano,
+    Copper,
+    Bone,
+    Grey,
+}
+
+impl Colormap {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "viridis" => Ok(Colormap::Viridis),
+            "vulcano" => Ok(Colormap::Vulcano),
+            "copper" => Ok(Colormap::Copper),
+            "bone" => Ok(Colormap::Bone),
+            "grey" | "gray" => Ok(Colormap::Grey),
+            _ => Err(anyhow!("unknown colormap {}", name)),
+        }
+    }
+
+    /// Colour for `x` from 0 to 1.
+    fn color(&self, x: f32) -> egui::Color32 {
+        let x = x.clamp(0.0, 1.0);
+        let (r, g, b) = match self {
+            Colormap::Viridis => ViridisRGB::get_color(x).rgb(),
+            Colormap::Vulcano => VulcanoHSL::get_color(x).rgb(),
+            Colormap::Copper => Copper::get_color(x).rgb(),
+            Colormap::Bone => Bone::get_color(x).rgb(),
+            Colormap::Grey => BlackWhite::get_color(x).rgb(),
+        };
+        egui::Color32::from_rgb(r, g, b)
+    }
+}
+
+/// How the spectrogram under each channel's spectrum is worked out and drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct SpectrogramConfig {
+    pub fft: usize,
+    /// fraction of each FFT frame shared with the next
+    pub overlap: f32,
+    /// dBFS mapped to the bottom and top of the colormap
+    pub floor_db: f32,
+    pub ceiling_db: f32,
+    pub colormap: Colormap,
+    /// FFT frames kept, the height of the image
+    pub rows: usize,
+}
+
+impl Default for SpectrogramConfig {
+    fn default() -> Self {
+        Self {
+            fft: 2048,
+            overlap: 0.5,
+            floor_db: FFT_FLOOR_DB,
+            ceiling_db: 0.0,
+            colormap: Colormap::default(),
+            rows: 200,
+        }
+    }
+}
+
+/// Successive spectra of a stream of samples, oldest first. Frames are shared
+/// between clones, so handing a copy to the display is cheap.
+#[derive(Clone)]
+pub struct Spectrogram {
+    pub config: SpectrogramConfig,
+    sample_rate: f32,
+    window: Window,
+    // samples not yet making up a whole frame
+    pending: Vec<f32>,
+    /// dBFS of bins 1..=fft/2 in each frame
+    pub frames: VecDeque<Arc<Vec<f32>>>,
+    /// frames made since the start, to tell when there are new ones
+    pub made: u64,
+}
+
+impl Spectrogram {
+    pub fn new(config: SpectrogramConfig, sample_rate: f32, window: Window) -> Self {
+        Self { config, sample_rate, window, pending: Vec::new(), frames: VecDeque::new(), made: 0 }
+    }
+
+    /// Samples between the starts of successive frames.
+    pub fn hop(&self) -> usize {
+        ((self.config.fft as f32 * (1.0 - self.config.overlap)).round() as usize).max(1)
+    }
+
+    /// Add the next run of samples, which must follow on from the last.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        let (n, hop) = (self.config.fft, self.hop());
+        let mut start = 0;
+        while start + n <= self.pending.len() {
+            let frame = spectrum::spectrum(&self.pending[start..start + n], self.sample_rate, self.window);
+            self.frames.push_back(Arc::new(frame.into_iter().map(|(_, db)| db).collect()));
+            self.made += 1;
+            if self.frames.len() > self.config.rows {
+                self.frames.pop_front();
+            }
+            start += hop;
+        }
+        self.pending.drain(..start.min(self.pending.len()));
+    }
+
+    fn bin_hz(&self, bin: usize) -> f32 {
+        (bin + 1) as f32 * self.sample_rate / self.config.fft as f32
+    }
+
+    // frequency at `x` of the way across, on the same log scale as the spectrum plot
+    fn x_to_hz(&self, x: f32) -> f32 {
+        let (lo, hi) = (self.bin_hz(0), self.bin_hz(self.config.fft / 2 - 1));
+        lo * (hi / lo).powf(x)
+    }
+
+    /// The frames as an image `width` wide, newest at the top. Each column shows
+    /// the loudest bin under it, so a narrow spur isn't lost where the columns
+    /// are many bins wide.
+    fn image(&self, width: usize) -> egui::ColorImage {
+        let bins = self.config.fft / 2;
+        let hz_to_bin = |f: f32| ((f * self.config.fft as f32 / self.sample_rate).round() as usize).clamp(1, bins) - 1;
+        let columns: Vec<(usize, usize)> = (0..width)
+            .map(|x| {
+                let lo = hz_to_bin(self.x_to_hz(x as f32 / width as f32));
+                let hi = hz_to_bin(self.x_to_hz((x + 1) as f32 / width as f32));
+                (lo, hi.max(lo + 1).min(bins))
+            })
+            .collect();
+        let range = self.config.ceiling_db - self.config.floor_db;
+        let mut pixels = Vec::with_capacity(width * self.config.rows);
+        for row in 0..self.config.rows {
+            match self.frames.iter().rev().nth(row) {
+                Some(frame) => pixels.extend(columns.iter().map(|(lo, hi)| {
+                    let db = frame[*lo..*hi].iter().fold(spectrum::DB_FLOOR, |m, db| m.max(*db));
+                    self.config.colormap.color((db - self.config.floor_db) / range)
+                })),
+                None => pixels.extend(std::iter::repeat_n(egui::Color32::BLACK, width)),
+            }
+        }
+        egui::ColorImage::new([width, self.config.rows], pixels)
+    }
+}
+
 #[derive(Clone)]
 pub struct ScopeChannel {
     pub name: String,
@@ -13,6 +262,14 @@ pub struct ScopeChannel {
     pub fft: Vec<(f32, f32)>,
     pub rms: f32,
     pub peak: f32,
+    pub spectrogram: Option<Spectrogram>,
+    /// seconds across the trace
+    pub span: f32,
+    /// mean of the capture, taken off with AC coupling
+    pub mean: f32,
+    /// drawn across the trace of the trigger source
+    pub trigger_level: Option<f32>,
+    pub view: View,
 }
 
 impl ScopeChannel {
@@ -23,19 +280,274 @@ impl ScopeChannel {
             fft: Vec::new(),
             rms: 0.0,
             peak: 0.0,
+            spectrogram: None,
+            span: 0.0,
+            mean: 0.0,
+            trigger_level: None,
+            view: View::default(),
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Slope {
+    #[default]
+    Rising,
+    Falling,
+    Either,
+}
+
+impl Slope {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "rising" | "rise" | "+" => Ok(Slope::Rising),
+            "falling" | "fall" | "-" => Ok(Slope::Falling),
+            "either" | "both" => Ok(Slope::Either),
+            _ => Err(anyhow!("unknown slope {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Slope::Rising => "rising",
+            Slope::Falling => "falling",
+            Slope::Either => "either",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum TriggerMode {
+    /// free runs when nothing triggers
+    #[default]
+    Auto,
+    /// only shows triggered captures
+    Normal,
+    /// shows one triggered capture, then holds it until armed again
+    Single,
+}
+
+impl TriggerMode {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "auto" => Ok(TriggerMode::Auto),
+            "normal" => Ok(TriggerMode::Normal),
+            "single" => Ok(TriggerMode::Single),
+            _ => Err(anyhow!("unknown trigger mode {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerMode::Auto => "auto",
+            TriggerMode::Normal => "normal",
+            TriggerMode::Single => "single",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trigger {
+    pub level: f32,
+    pub slope: Slope,
+    /// index into the scope's channels
+    pub source: usize,
+    /// seconds after a trigger before the next can be taken
+    pub holdoff: f32,
+    pub mode: TriggerMode,
+}
+
+impl Trigger {
+    /// Index of the first crossing of the level in `samples[from..to]`.
+    pub fn find(&self, samples: &[f32], from: usize, to: usize) -> Option<usize> {
+        (from.max(1)..to.min(samples.len())).find(|i| {
+            let (prev, s) = (samples[i - 1], samples[*i]);
+            let rising = prev <= self.level && s > self.level;
+            let falling = prev >= self.level && s < self.level;
+            match self.slope {
+                Slope::Rising => rising,
+                Slope::Falling => falling,
+                Slope::Either => rising || falling,
+            }
+        })
+    }
+}
+
+// most points kept from each capture for the XY display
+const XY_MAX_POINTS: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum XyMode {
+    #[default]
+    Off,
+    /// the first channel across, the second up
+    Xy,
+    /// the pair as left and right, turned 45 degrees so mid is up and side across
+    Goniometer,
+}
+
+impl XyMode {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "off" => Ok(XyMode::Off),
+            "xy" => Ok(XyMode::Xy),
+            "gonio" | "goniometer" => Ok(XyMode::Goniometer),
+            _ => Err(anyhow!("unknown xy mode {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            XyMode::Off => "off",
+            XyMode::Xy => "xy",
+            XyMode::Goniometer => "goniometer",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct XyConfig {
+    pub mode: XyMode,
+    /// indexes into the scope's channels
+    pub x: usize,
+    pub y: usize,
+    /// captures kept on screen, fading with age
+    pub persistence: usize,
+}
+
+impl Default for XyConfig {
+    fn default() -> Self {
+        Self { mode: XyMode::Off, x: 0, y: 1, persistence: 8 }
+    }
+}
+
+/// The last few captures of a channel pair, for the XY display.
+#[derive(Clone, Default)]
+pub struct XyTrace {
+    pub mode: XyMode,
+    pub labels: (String, String),
+    /// oldest first
+    pub frames: VecDeque<Vec<(f32, f32)>>,
+    /// of the latest capture
+    pub correlation: f32,
+}
+
+impl XyTrace {
+    /// Add a capture of the pair, `x` and `y` sample for sample.
+    pub fn push(&mut self, config: &XyConfig, x: &[f32], y: &[f32]) {
+        if config.mode != self.mode {
+            self.frames.clear();
+            self.mode = config.mode;
+        }
+        let stride = x.len().div_ceil(XY_MAX_POINTS).max(1);
+        let points = x.iter().zip(y).step_by(stride).map(|(a, b)| match config.mode {
+            XyMode::Goniometer => ((b - a) * std::f32::consts::FRAC_1_SQRT_2, (a + b) * std::f32::consts::FRAC_1_SQRT_2),
+            _ => (*a, *b),
+        });
+        self.frames.push_back(points.collect());
+        while self.frames.len() > config.persistence.max(1) {
+            self.frames.pop_front();
+        }
+        self.correlation = correlation::phase_correlation(x, y);
+    }
+}
+
+impl TuiWidget for XyTrace {
+    type Response = egui::Response;
+
+    fn taffy_ui(self, tuib: TuiBuilder) -> Self::Response {
+        tuib.ui_add_manual(|ui| {
+            ui.vertical(|ui| {
+                let frame = egui::Frame::new()
+                    .corner_radius(20.0);
+                frame.show(ui, |ui| {
+                    ui.set_width(300.0);
+                    ui.set_height(300.0);
+
+                    let root = EguiBackend::new(ui).into_drawing_area();
+                    root.fill(&BLACK).unwrap();
+                    let (x_desc, y_desc) = match self.mode {
+                        XyMode::Goniometer => (format!("S  {}-{}", self.labels.1, self.labels.0), format!("M  {}+{}", self.labels.0, self.labels.1)),
+                        _ => self.labels.clone(),
+                    };
+                    let mut chart = ChartBuilder::on(&root)
+                        .margin(5)
+                        .x_label_area_size(30)
+                        .y_label_area_size(30)
+                        .build_cartesian_2d(-1f32..1f32, -1f32..1f32)
+                        .unwrap();
+
+                    chart.configure_mesh()
+                        .x_labels(5)
+                        .y_labels(5)
+                        .x_desc(x_desc)
+                        .y_desc(y_desc)
+                        .axis_style(WHITE)
+                        .label_style(("sans-serif", 10).into_font().color(&WHITE))
+                        .axis_desc_style(("sans-serif", 10).into_font().color(&WHITE))
+                        .draw().unwrap();
+
+                    // older captures fainter
+                    let n = self.frames.len();
+                    for (age, points) in self.frames.iter().rev().enumerate() {
+                        let alpha = 1.0 - age as f64 / n as f64;
+                        chart
+                            .draw_series(LineSeries::new(points.iter().map(|(x, y)| (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0))), GREEN.mix(alpha)))
+                            .unwrap();
+                    }
+
+                    root.present().unwrap();
+                });
+
+                // correlation meter, -1 on the left to +1 on the right
+                let (rect, _) = ui.allocate_exact_size(egui::vec2(300.0, 14.0), egui::Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 2.0, egui::Color32::from_gray(40));
+                let centre = rect.center().x;
+                let end = centre + self.correlation.clamp(-1.0, 1.0) * rect.width() / 2.0;
+                let color = if self.correlation < 0.0 { egui::Color32::RED } else { egui::Color32::GREEN };
+                painter.rect_filled(egui::Rect::from_x_y_ranges(centre.min(end)..=centre.max(end), rect.y_range()), 0.0, color);
+                painter.vline(centre, rect.y_range(), egui::Stroke::new(1.0, egui::Color32::WHITE));
+                ui.add(Label::new(RichText::new(format!("correlation {:+.2}", self.correlation)).monospace()));
+            }).response
+        },
+        |response, _ui| response)
+    }
+}
+
+/// State shared between the capture thread and the scope window.
 pub struct Scope {
     pub data: Mutex<Vec<ScopeChannel>>,
+    pub trigger: Mutex<Trigger>,
+    /// seconds per division
+    pub timebase: Mutex<f32>,
+    /// one per channel
+    pub views: Mutex<Vec<View>>,
+    pub xy: Mutex<XyConfig>,
+    pub xy_trace: Mutex<XyTrace>,
+    /// a single capture is wanted
+    pub armed: AtomicBool,
+    /// whether the last capture found a trigger
+    pub triggered: AtomicBool,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scope {
     pub fn new() -> Self {
         Self {
-            data: Mutex::new(Vec::new())
+            data: Mutex::new(Vec::new()),
+            trigger: Mutex::new(Trigger::default()),
+            timebase: Mutex::new(1e-3),
+            views: Mutex::new(Vec::new()),
+            xy: Mutex::new(XyConfig::default()),
+            xy_trace: Mutex::new(XyTrace::default()),
+            armed: AtomicBool::new(true),
+            triggered: AtomicBool::new(false),
         }
     }
 }
@@ -51,7 +563,7 @@ struct ScopeBuilder {
 }
 
 impl ScopeBuilder {
-    fn new(cc: &eframe::CreationContext<'_>, ctl: Arc<Scope>) -> Self {
+    fn new(_cc: &eframe::CreationContext<'_>, ctl: Arc<Scope>) -> Self {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
@@ -60,6 +572,107 @@ impl ScopeBuilder {
     }
 }
 
+// pixels either side of the pointer searched for a peak to snap a cursor to
+const SNAP_PX: f32 = 8.0;
+
+/// A pair of cursors on a plot, in the plot's units.
+#[derive(Clone, Copy, Debug, Default)]
+struct Cursors {
+    a: Option<(f32, f32)>,
+    b: Option<(f32, f32)>,
+}
+
+impl Cursors {
+    // b - a, once both are placed
+    fn delta(&self) -> Option<(f32, f32)> {
+        let (a, b) = (self.a?, self.b?);
+        Some((b.0 - a.0, b.1 - a.1))
+    }
+}
+
+// between positions on screen and values on a chart
+struct PlotMap {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+    x: (f32, f32),
+    y: (f32, f32),
+    log_x: bool,
+}
+
+impl PlotMap {
+    fn new<DB: DrawingBackend, CT: CoordTranslate>(ui: &egui::Ui, area: &DrawingArea<DB, CT>, x: (f32, f32), y: (f32, f32), log_x: bool) -> Self {
+        // the egui backend draws from the top left of the ui
+        let origin = ui.max_rect().min;
+        let (px, py) = area.get_pixel_range();
+        Self {
+            left: origin.x + px.start as f32,
+            right: origin.x + px.end as f32,
+            top: origin.y + py.start as f32,
+            bottom: origin.y + py.end as f32,
+            x,
+            y,
+            log_x,
+        }
+    }
+
+    fn value(&self, pos: egui::Pos2) -> (f32, f32) {
+        let fx = ((pos.x - self.left) / (self.right - self.left)).clamp(0.0, 1.0);
+        let fy = ((pos.y - self.top) / (self.bottom - self.top)).clamp(0.0, 1.0);
+        let x = if self.log_x { self.x.0 * (self.x.1 / self.x.0).powf(fx) } else { self.x.0 + fx * (self.x.1 - self.x.0) };
+        (x, self.y.1 - fy * (self.y.1 - self.y.0))
+    }
+
+    fn screen_x(&self, x: f32) -> f32 {
+        let fx = if self.log_x { (x / self.x.0).ln() / (self.x.1 / self.x.0).ln() } else { (x - self.x.0) / (self.x.1 - self.x.0) };
+        self.left + fx * (self.right - self.left)
+    }
+
+    // the biggest of `points`, by `size`, within a few pixels of `x`
+    fn snap(&self, points: &[(f32, f32)], x: f32, size: impl Fn(f32) -> f32) -> Option<(f32, f32)> {
+        let sx = self.screen_x(x);
+        points.iter()
+            .filter(|(px, _)| (self.screen_x(*px) - sx).abs() <= SNAP_PX)
+            .max_by(|a, b| size(a.1).total_cmp(&size(b.1)))
+            .copied()
+    }
+}
+
+// Left button places or drags cursor A, right button B, and a double click
+// clears them. A cursor snaps to the biggest point nearby unless alt is held.
+fn cursors_ui<X, Y>(ui: &egui::Ui, id: egui::Id, chart: &mut ChartContext<EguiBackend, Cartesian2d<X, Y>>, map: &PlotMap, points: &[(f32, f32)], size: impl Fn(f32) -> f32) -> Cursors
+where
+    X: Ranged<ValueType = f32>,
+    Y: Ranged<ValueType = f32>,
+{
+    let mut cursors = ui.ctx().data(|d| d.get_temp::<Cursors>(id)).unwrap_or_default();
+    let response = ui.interact(ui.max_rect(), id, egui::Sense::click_and_drag());
+    if response.double_clicked() {
+        cursors = Cursors::default();
+    } else if let Some(pos) = response.interact_pointer_pos() {
+        let (x, y) = map.value(pos);
+        let free = ui.input(|i| i.modifiers.alt);
+        let at = if free { (x, y) } else { map.snap(points, x, size).unwrap_or((x, y)) };
+        for (button, cursor) in [(egui::PointerButton::Primary, &mut cursors.a), (egui::PointerButton::Secondary, &mut cursors.b)] {
+            if response.dragged_by(button) || response.clicked_by(button) {
+                *cursor = Some(at);
+            }
+        }
+    }
+    ui.ctx().data_mut(|d| d.insert_temp(id, cursors));
+
+    for (cursor, color) in [(cursors.a, CYAN), (cursors.b, MAGENTA)] {
+        if let Some((x, y)) = cursor {
+            let (x, y) = (x.clamp(map.x.0, map.x.1), y.clamp(map.y.0, map.y.1));
+            chart.draw_series(DashedLineSeries::new([(x, map.y.0), (x, map.y.1)], 3, 3, color.into())).unwrap();
+            chart.draw_series(DashedLineSeries::new([(map.x.0, y), (map.x.1, y)], 3, 3, color.into())).unwrap();
+            chart.draw_series(std::iter::once(Circle::new((x, y), 3, color.filled()))).unwrap();
+        }
+    }
+    cursors
+}
+
 impl TuiWidget for ScopeChannel {
     type Response = egui::Response;
 
@@ -68,29 +681,46 @@ impl TuiWidget for ScopeChannel {
             let rms = self.rms;
             let peak = self.peak;
             ui.vertical(|ui| {
-                if let Some(last_sample) = self.samples.last() {
+                if !self.samples.is_empty() {
+                    let db = matches!(self.view.vertical, Vertical::Db(_));
                     let frame = egui::Frame::new()
                         .corner_radius(20.0);
-                    frame.show(ui, |ui| {
+                    let cursors = frame.show(ui, |ui| {
                         ui.set_width(400.0);
                         ui.set_height(300.0);
 
                         let root = EguiBackend::new(ui).into_drawing_area();
                         root.fill(&BLACK).unwrap();
+                        let (bottom, top) = self.view.range();
                         let mut chart = ChartBuilder::on(&root)
                             .margin(5)
                             .x_label_area_size(30)
-                            .y_label_area_size(30)
-                            .build_cartesian_2d(0f32..last_sample.0, -1f32..1f32)
+                            .y_label_area_size(40)
+                            .build_cartesian_2d(0f32..self.span, bottom..top)
                             .unwrap();
 
                         chart.configure_mesh()
+                            .x_labels(DIVISIONS_X + 1)
+                            .y_labels(DIVISIONS_Y + 1)
+                            .x_label_formatter(&|t| format!("{:.3}", t * 1000.0))
+                            .x_desc("ms")
                             .axis_style(WHITE)
                             .label_style(("sans-serif", 10).into_font().color(&WHITE))
+                            .axis_desc_style(("sans-serif", 10).into_font().color(&WHITE))
                             .draw().unwrap();
 
+                        if let Some(level) = self.trigger_level {
+                            let level = self.view.plot(level, self.mean);
+                            chart
+                                .draw_series(DashedLineSeries::new([(0.0, level), (self.span, level)], 4, 4, RED.into()))
+                                .unwrap();
+                        }
+
+                        let points: Vec<(f32, f32)> = self.samples.iter()
+                            .map(|(t, s)| (*t, self.view.plot(*s, self.mean).clamp(bottom, top)))
+                            .collect();
                         chart
-                            .draw_series(LineSeries::new(self.samples.clone(), &GREEN))
+                            .draw_series(LineSeries::new(points.iter().copied(), &GREEN))
                             .unwrap();
 
                         // chart
@@ -100,64 +730,245 @@ impl TuiWidget for ScopeChannel {
                         //     .draw()
                         //     .unwrap();
 
+                        let map = PlotMap::new(ui, chart.plotting_area(), (0.0, self.span), (bottom, top), false);
+                        let id = egui::Id::new(&self.name).with("scope cursors");
+                        // snap to the tallest excursion either way, or the loudest on a dB scale
+                        let cursors = cursors_ui(ui, id, &mut chart, &map, &points, |y| if db { y } else { y.abs() });
+
+                        root.present().unwrap();
+                        cursors
+                    }).inner;
+                    let level = |y: f32| if db { format!("{y:.1} dBFS") } else { format!("{y:+.4}") };
+                    let at = |c: Option<(f32, f32)>| c.map_or(String::from("-"), |(t, y)| format!("{:.3} ms {}", t * 1e3, level(y)));
+                    let mut readout = format!("A {}  B {}", at(cursors.a), at(cursors.b));
+                    if let (Some(a), Some(b), Some((dt, dy))) = (cursors.a, cursors.b, cursors.delta()) {
+                        let freq = if dt == 0.0 { String::from("-") } else { format!("{:.1} Hz", 1.0 / dt.abs()) };
+                        readout += &format!("\n\u{394}t {:.3} ms  1/\u{394}t {freq}", dt * 1e3);
+                        readout += &if db {
+                            format!("  \u{394}dB {dy:+.2}")
+                        } else {
+                            format!("  \u{394}V {dy:+.4}  \u{394}dB {:+.2}", spectrum::to_db(b.1.abs()) - spectrum::to_db(a.1.abs()))
+                        };
+                    }
+                    ui.add(Label::new(RichText::new(readout).monospace()));
+                }
+                if let (Some(first_bin), Some(last_bin)) = (self.fft.first(), self.fft.last()) {
+                    let frame = egui::Frame::new()
+                        .corner_radius(20.0);
+                    let cursors = frame.show(ui, |ui| {
+                        ui.set_width(400.0);
+                        ui.set_height(200.0);
+
+                        let root = EguiBackend::new(ui).into_drawing_area();
+                        root.fill(&BLACK).unwrap();
+                        let mut chart = ChartBuilder::on(&root)
+                            .margin(5)
+                            .x_label_area_size(30)
+                            .y_label_area_size(30)
+                            .build_cartesian_2d((first_bin.0..last_bin.0).log_scale(), FFT_FLOOR_DB..0f32)
+                            .unwrap();
+
+                        chart.configure_mesh()
+                            .axis_style(WHITE)
+                            .label_style(("sans-serif", 10).into_font().color(&WHITE))
+                            .draw().unwrap();
+
+                        let points: Vec<(f32, f32)> = self.fft.iter().map(|(f, db)| (*f, db.max(FFT_FLOOR_DB))).collect();
+                        chart
+                            .draw_series(LineSeries::new(points.iter().copied(), &YELLOW))
+                            .unwrap();
+
+                        let map = PlotMap::new(ui, chart.plotting_area(), (first_bin.0, last_bin.0), (FFT_FLOOR_DB, 0.0), true);
+                        let id = egui::Id::new(&self.name).with("spectrum cursors");
+                        let cursors = cursors_ui(ui, id, &mut chart, &map, &points, |db| db);
+
                         root.present().unwrap();
-                    });
+                        cursors
+                    }).inner;
+                    let at = |c: Option<(f32, f32)>| c.map_or(String::from("-"), |(f, db)| format!("{f:.1} Hz {db:.1} dBFS"));
+                    let mut readout = format!("A {}  B {}", at(cursors.a), at(cursors.b));
+                    if let Some((df, ddb)) = cursors.delta() {
+                        readout += &format!("\n\u{394}f {df:.1} Hz  \u{394}dB {ddb:+.2}");
+                    }
+                    ui.add(Label::new(RichText::new(readout).monospace()));
+                }
+                if let Some(spectrogram) = self.spectrogram.as_ref().filter(|s| !s.frames.is_empty()) {
+                    let width = 400;
+                    // one texture per channel, kept from frame to frame and only redrawn
+                    // when there are new spectra
+                    let id = ui.id().with(&self.name).with("spectrogram");
+                    let texture = ui.ctx().data_mut(|d| d.get_temp::<(egui::TextureHandle, u64)>(id));
+                    let texture = match texture {
+                        Some((t, made)) if made == spectrogram.made => t,
+                        Some((mut t, _)) => {
+                            t.set(spectrogram.image(width), egui::TextureOptions::NEAREST);
+                            t
+                        },
+                        None => ui.ctx().load_texture(format!("{}-spectrogram", self.name), spectrogram.image(width), egui::TextureOptions::NEAREST),
+                    };
+                    ui.ctx().data_mut(|d| d.insert_temp(id, (texture.clone(), spectrogram.made)));
+                    let size = egui::vec2(width as f32, 150.0);
+                    let response = ui.add(egui::Image::new((texture.id(), size)).sense(egui::Sense::hover()));
+                    // read off where the pointer is
+                    if let Some(pos) = response.hover_pos() {
+                        let x = (pos.x - response.rect.left()) / response.rect.width();
+                        let y = (pos.y - response.rect.top()) / response.rect.height();
+                        let row = (y * spectrogram.config.rows as f32) as usize;
+                        let f = spectrogram.x_to_hz(x);
+                        let ago = row as f32 * spectrogram.hop() as f32 / spectrogram.sample_rate;
+                        let bin = ((f * spectrogram.config.fft as f32 / spectrogram.sample_rate).round() as usize).max(1) - 1;
+                        let level = spectrogram.frames.iter().rev().nth(row).and_then(|frame| frame.get(bin));
+                        response.on_hover_text_at_pointer(match level {
+                            Some(db) => format!("{f:.0} Hz  -{ago:.2} s  {db:.1} dBFS"),
+                            None => format!("{f:.0} Hz"),
+                        });
+                    }
                 }
                 ui.add(Label::new(RichText::new(format!("rms {rms} peak {peak}")).monospace()));
             }).response
         },
-        |mut response, _ui| response)
+        |response, _ui| response)
     }
 }
 
-fn scope_channel(channel: &ScopeChannel) -> impl egui::Widget + '_ {
-    move |ui: &mut egui::Ui| {
-        let rms = channel.rms;
-        let peak = channel.peak;
-        ui.vertical(|ui| {
-            if let Some(last_sample) = channel.samples.last() {
-                let frame = egui::Frame::new()
-                    .corner_radius(20.0);
-                frame.show(ui, |ui| {
-                    ui.set_width(400.0);
-                    ui.set_height(300.0);
+impl ScopeBuilder {
+    fn trigger_controls(&self, ui: &mut egui::Ui) {
+        let names: Vec<String> = self.ctl.data.lock().unwrap().iter().map(|d| d.name.clone()).collect();
+        let mut trigger = self.ctl.trigger.lock().unwrap();
+        ui.horizontal(|ui| {
+            ui.label("trigger");
+            egui::ComboBox::from_id_salt("trigger mode")
+                .selected_text(trigger.mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in [TriggerMode::Auto, TriggerMode::Normal, TriggerMode::Single] {
+                        ui.selectable_value(&mut trigger.mode, mode, mode.name());
+                    }
+                });
+            egui::ComboBox::from_id_salt("trigger source")
+                .selected_text(names.get(trigger.source).cloned().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for (i, name) in names.iter().enumerate() {
+                        ui.selectable_value(&mut trigger.source, i, name);
+                    }
+                });
+            egui::ComboBox::from_id_salt("trigger slope")
+                .selected_text(trigger.slope.name())
+                .show_ui(ui, |ui| {
+                    for slope in [Slope::Rising, Slope::Falling, Slope::Either] {
+                        ui.selectable_value(&mut trigger.slope, slope, slope.name());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut trigger.level, -1.0..=1.0).text("level"));
+            let mut holdoff_ms = trigger.holdoff * 1000.0;
+            if ui.add(egui::DragValue::new(&mut holdoff_ms).range(0.0..=10_000.0).suffix(" ms holdoff")).changed() {
+                trigger.holdoff = holdoff_ms / 1000.0;
+            }
+            let armed = self.ctl.armed.load(Ordering::Relaxed);
+            if trigger.mode == TriggerMode::Single && ui.add_enabled(!armed, egui::Button::new("arm")).clicked() {
+                self.ctl.armed.store(true, Ordering::Relaxed);
+            }
+            let status = match (trigger.mode, self.ctl.triggered.load(Ordering::Relaxed)) {
+                (TriggerMode::Single, _) if !armed => "stopped",
+                (_, true) => "trig'd",
+                (TriggerMode::Auto, false) => "auto",
+                (_, false) => "waiting",
+            };
+            ui.label(RichText::new(status).monospace());
+        });
+        drop(trigger);
 
-                    let root = EguiBackend::new(ui).into_drawing_area();
-                    root.fill(&BLACK).unwrap();
-                    let mut chart = ChartBuilder::on(&root)
-                        .margin(5)
-                        .x_label_area_size(30)
-                        .y_label_area_size(30)
-                        .build_cartesian_2d(0f32..last_sample.0, -1f32..1f32)
-                        .unwrap();
+        ui.horizontal(|ui| {
+            let mut timebase = self.ctl.timebase.lock().unwrap();
+            ui.label("timebase");
+            if ui.button("-").clicked() {
+                *timebase = step_125(*timebase, false).max(MIN_TIMEBASE);
+            }
+            ui.label(RichText::new(format!("{:>8}/div", time_label(*timebase))).monospace());
+            if ui.button("+").clicked() {
+                *timebase = step_125(*timebase, true).min(MAX_TIMEBASE);
+            }
+        });
 
-                    chart.configure_mesh()
-                        .axis_style(WHITE)
-                        .label_style(("sans-serif", 10).into_font().color(&WHITE))
-                        .draw().unwrap();
+        ui.horizontal(|ui| {
+            let mut guard = self.ctl.xy.lock().unwrap();
+            let xy = &mut *guard;
+            ui.label("xy");
+            for mode in [XyMode::Off, XyMode::Xy, XyMode::Goniometer] {
+                ui.selectable_value(&mut xy.mode, mode, mode.name());
+            }
+            if xy.mode != XyMode::Off {
+                let labels = if xy.mode == XyMode::Goniometer { ["L", "R"] } else { ["x", "y"] };
+                for (label, source) in labels.into_iter().zip([&mut xy.x, &mut xy.y]) {
+                    egui::ComboBox::from_id_salt(label)
+                        .selected_text(format!("{label} {}", names.get(*source).cloned().unwrap_or_default()))
+                        .show_ui(ui, |ui| {
+                            for (i, name) in names.iter().enumerate() {
+                                ui.selectable_value(source, i, name);
+                            }
+                        });
+                }
+                ui.add(egui::DragValue::new(&mut xy.persistence).range(1..=64).suffix(" persistence"));
+            }
+        });
 
-                    chart
-                        .draw_series(LineSeries::new(channel.samples.clone(), &GREEN))
-                        .unwrap();
+        let mut views = self.ctl.views.lock().unwrap();
+        views.resize(names.len(), View::default());
+        for (name, view) in names.iter().zip(views.iter_mut()) {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new(name).monospace());
+                let db = matches!(view.vertical, Vertical::Db(_));
+                // switching scale starts the new one at its default
+                if ui.selectable_label(!db, "lin").clicked() && db {
+                    view.vertical = View::default().vertical;
+                    view.offset = 0.0;
+                }
+                if ui.selectable_label(db, "dBFS").clicked() && !db {
+                    view.vertical = Vertical::Db(10.0);
+                    view.offset = 0.0;
+                }
+                let per_div = match &mut view.vertical {
+                    Vertical::Linear(x) | Vertical::Db(x) => x,
+                };
+                if ui.button("-").clicked() {
+                    *per_div = step_125(*per_div, false).max(1e-6);
+                }
+                let unit = if db { "dB" } else { "FS" };
+                ui.label(RichText::new(format!("{:>6} {unit}/div", knob_label(*per_div))).monospace());
+                if ui.button("+").clicked() {
+                    *per_div = step_125(*per_div, true).min(if db { 50.0 } else { 1.0 });
+                }
+                ui.add(egui::DragValue::new(&mut view.offset).range(-(DIVISIONS_Y as f32)..=DIVISIONS_Y as f32).speed(0.05).suffix(" div"));
+                for coupling in [Coupling::Dc, Coupling::Ac] {
+                    ui.selectable_value(&mut view.coupling, coupling, coupling.name());
+                }
+            });
+        }
+    }
+}
 
-                    // chart
-                    //     .configure_series_labels()
-                    //     .background_style(WHITE.mix(0.8))
-                    //     .border_style(BLACK)
-                    //     .draw()
-                    //     .unwrap();
+// enough decimals to show the two leading digits, without trailing zeros
+fn knob_label(x: f32) -> String {
+    let decimals = (1.0 - x.log10().floor()).max(0.0) as usize;
+    let s = format!("{x:.decimals$}");
+    if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.').to_string() } else { s }
+}
 
-                    root.present().unwrap();
-                });
-            }
-            ui.add(Label::new(RichText::new(format!("rms {rms} peak {peak}")).monospace()));
-        }).response
+fn time_label(t: f32) -> String {
+    if t < 1e-3 {
+        format!("{:.0} us", t * 1e6)
+    } else if t < 1.0 {
+        format!("{:.0} ms", t * 1e3)
+    } else {
+        format!("{t:.0} s")
     }
 }
 
 impl eframe::App for ScopeBuilder {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let channel_ct = self.ctl.data.lock().unwrap().len();
+        let trigger = *self.ctl.trigger.lock().unwrap();
+        let views = self.ctl.views.lock().unwrap().clone();
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.trigger_controls(ui));
         egui::CentralPanel::default().show(ctx, |ui| {
             tui(ui, ui.id().with("demo"))
                 .reserve_available_space()
@@ -166,9 +977,17 @@ impl eframe::App for ScopeBuilder {
                     ..Default::default()
                 })
                 .show(|tui| {
+                    // the trace keeps its last frames once the mode is off, so go by the control
+                    let mode = self.ctl.xy.lock().unwrap().mode;
+                    let xy = self.ctl.xy_trace.lock().unwrap().clone();
+                    if mode != XyMode::Off && !xy.frames.is_empty() {
+                        tui.ui_add(xy);
+                    }
                     for i in 0..channel_ct {
-                        let data = self.ctl.data.lock().unwrap();
-                        tui.ui_add(data[i].clone());
+                        let mut d = self.ctl.data.lock().unwrap()[i].clone();
+                        d.trigger_level = (i == trigger.source).then_some(trigger.level);
+                        d.view = views.get(i).copied().unwrap_or_default();
+                        tui.ui_add(d);
                     }
                 });
         });
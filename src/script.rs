@@ -0,0 +1,366 @@
+// A small s-expression script language for sequencing autt commands.
+//
+// A script is a sequence of forms, each `(name args...)`. Two forms are
+// handled here:
+//
+//   (set name value)             bind a variable
+//   (for name (v1 v2 ...) body...) run body once per value, with name bound
+//
+// Variables are referenced as $name, e.g. `(sinout :freq $f)`, so that a
+// variable can share its name with a key. Every other form is handed, with
+// variables substituted, to the caller's command function. Errors are reported with the file, line and column of
+// the form that caused them.
+//
+// Numbers may carry a unit, like -3dB or 1kHz. The s-expression reader
+// doesn't accept those, so they are turned into strings before parsing and
+// the as_* helpers below interpret them.
+
+use anyhow::{anyhow, Result};
+use lexpr::{datum::Ref, Datum, Parser, Value};
+use std::collections::HashMap;
+
+pub struct Script {
+    name: String,
+    forms: Vec<Datum>,
+    // (line, column) of each quote added around a unit, to correct error positions
+    quotes: Vec<(usize, usize)>,
+}
+
+impl Script {
+    pub fn from_str(name: &str, text: &str) -> Result<Self> {
+        let (text, quotes) = quote_units(text);
+        let quotes = positions(&text, &quotes);
+        let mut parser = Parser::from_str(&text);
+        let mut forms = Vec::new();
+        loop {
+            match parser.next_datum() {
+                Ok(Some(datum)) => forms.push(datum),
+                Ok(None) => break,
+                Err(e) => return Err(match e.location() {
+                    // lexpr's columns count from 1 here, and its message ends with the position
+                    Some(at) => {
+                        let msg = e.to_string();
+                        let msg = msg.split(" at line ").next().unwrap_or(&msg);
+                        let column = unquoted_column(&quotes, at.line(), at.column().saturating_sub(1)) + 1;
+                        anyhow!("{}:{}:{}: {}", name, at.line(), column, msg)
+                    },
+                    None => anyhow!("{}: {}", name, e),
+                }),
+            }
+        }
+        Ok(Self { name: name.to_string(), forms, quotes })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path, e))?;
+        Self::from_str(path, &text)
+    }
+
+    /// Evaluate the script, calling `exec(name, args)` for each command form.
+    pub fn run<F>(&self, mut exec: F) -> Result<()>
+        where F: FnMut(&str, &Value) -> Result<()>
+    {
+        let mut interp = Interp { name: &self.name, quotes: &self.quotes, vars: HashMap::new() };
+        for form in &self.forms {
+            interp.eval(form.as_ref(), &mut exec)?;
+        }
+        Ok(())
+    }
+}
+
+struct Interp<'a> {
+    name: &'a str,
+    quotes: &'a [(usize, usize)],
+    vars: HashMap<String, Value>,
+}
+
+impl Interp<'_> {
+    fn error(&self, at: &Ref, e: anyhow::Error) -> anyhow::Error {
+        let pos = at.span().start();
+        let column = unquoted_column(self.quotes, pos.line(), pos.column());
+        anyhow!("{}:{}:{}: {}", self.name, pos.line(), column + 1, e)
+    }
+
+    fn eval<F>(&mut self, form: Ref, exec: &mut F) -> Result<()>
+        where F: FnMut(&str, &Value) -> Result<()>
+    {
+        let Some((head, rest)) = form.as_pair() else {
+            return Err(self.error(&form, anyhow!("expected a command, got {}", form.value())));
+        };
+        let Some(name) = head.as_symbol() else {
+            return Err(self.error(&head, anyhow!("expected a command name, got {}", head.value())));
+        };
+        let args: Vec<Ref> = match rest.list_iter() {
+            Some(i) => i.collect(),
+            None => return Err(self.error(&form, anyhow!("{}: malformed argument list", name))),
+        };
+
+        match name {
+            "set" => {
+                let [var, val] = args[..] else {
+                    return Err(self.error(&form, anyhow!("set: expected (set name value)")));
+                };
+                let Some(var) = var.as_symbol() else {
+                    return Err(self.error(&var, anyhow!("set: expected a variable name, got {}", var.value())));
+                };
+                let val = self.substitute(val.value()).map_err(|e| self.error(&form, anyhow!("set: {}", e)))?;
+                self.vars.insert(var.to_string(), val);
+                Ok(())
+            },
+            "for" => {
+                if args.len() < 2 {
+                    return Err(self.error(&form, anyhow!("for: expected (for name (values...) body...)")));
+                }
+                let Some(var) = args[0].as_symbol() else {
+                    return Err(self.error(&args[0], anyhow!("for: expected a variable name, got {}", args[0].value())));
+                };
+                let values = self.substitute(args[1].value()).map_err(|e| self.error(&args[1], anyhow!("for: {}", e)))?;
+                let Some(values) = values.list_iter() else {
+                    return Err(self.error(&args[1], anyhow!("for: expected a list of values, got {}", values)));
+                };
+                let values: Vec<Value> = values.cloned().collect();
+                for v in values {
+                    self.vars.insert(var.to_string(), v);
+                    for body in &args[2..] {
+                        self.eval(*body, exec)?;
+                    }
+                }
+                Ok(())
+            },
+            _ => {
+                let args = self.substitute(rest.value()).map_err(|e| self.error(&form, anyhow!("{}: {}", name, e)))?;
+                exec(name, &args).map_err(|e| self.error(&form, anyhow!("{}: {}", name, e)))
+            },
+        }
+    }
+
+    // replace $name references with their values, anywhere in `v`
+    fn substitute(&self, v: &Value) -> Result<Value> {
+        match v {
+            Value::Symbol(s) => match s.strip_prefix('$') {
+                Some(var) => self.vars.get(var).cloned().ok_or_else(|| anyhow!("{} is not set", var)),
+                None => Ok(v.clone()),
+            },
+            Value::Cons(_) => match v.list_iter() {
+                Some(i) => Ok(Value::list(i.map(|x| self.substitute(x)).collect::<Result<Vec<_>>>()?)),
+                None => Ok(v.clone()),
+            },
+            _ => Ok(v.clone()),
+        }
+    }
+}
+
+const UNITS: &[&str] = &["dB", "dBFS", "%", "Hz", "kHz", "s", "ms"];
+
+// Wrap tokens like -3dB in quotes so the reader takes them as strings, and
+// return the byte offsets of the quotes added. Tokens inside strings and
+// comments are left alone.
+fn quote_units(text: &str) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(text.len());
+    let mut quotes = Vec::new();
+    let mut chars = text.chars();
+    let mut token = String::new();
+    let mut flush = |token: &mut String, out: &mut String| {
+        if split_unit(token).is_some_and(|(num, unit)| !unit.is_empty() && num.parse::<f32>().is_ok()) {
+            quotes.push(out.len());
+            out.push('"');
+            out.push_str(token);
+            quotes.push(out.len());
+            out.push('"');
+        } else {
+            out.push_str(token);
+        }
+        token.clear();
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                flush(&mut token, &mut out);
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => if let Some(c) = chars.next() { out.push(c) },
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            },
+            ';' => {
+                flush(&mut token, &mut out);
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '(' | ')' | '[' | ']' => {
+                flush(&mut token, &mut out);
+                out.push(c);
+            },
+            c if c.is_whitespace() => {
+                flush(&mut token, &mut out);
+                out.push(c);
+            },
+            c => token.push(c),
+        }
+    }
+    flush(&mut token, &mut out);
+    (out, quotes)
+}
+
+// line (from 1) and column (from 0) of each byte offset, as lexpr counts them
+fn positions(text: &str, offsets: &[usize]) -> Vec<(usize, usize)> {
+    offsets.iter().map(|&at| {
+        let before = &text[..at];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (1 + before.matches('\n').count(), at - line_start)
+    }).collect()
+}
+
+// map a column in the quoted text back to the text as written
+fn unquoted_column(quotes: &[(usize, usize)], line: usize, column: usize) -> usize {
+    column - quotes.iter().filter(|&&(l, c)| l == line && c < column).count()
+}
+
+// split "-3dB" into ("-3", "dB") if the suffix is a known unit
+fn split_unit(s: &str) -> Option<(&str, &str)> {
+    let at = s.find(|c: char| c.is_ascii_alphabetic() || c == '%').unwrap_or(s.len());
+    let (num, unit) = s.split_at(at);
+    if unit.is_empty() || UNITS.contains(&unit) { Some((num, unit)) } else { None }
+}
+
+fn quantity(val: &Value) -> Result<(f32, &str)> {
+    if let Some(x) = val.as_f64() {
+        return Ok((x as f32, ""));
+    }
+    let parsed = val.as_str()
+        .and_then(split_unit)
+        .and_then(|(num, unit)| num.parse::<f32>().ok().map(|x| (x, unit)));
+    parsed.ok_or_else(|| anyhow!("expected a number, got {}", val))
+}
+
+/// Parse a single s-expression, such as a command line argument, allowing
+/// numbers with units.
+pub fn parse_args(text: &str) -> Result<Value> {
+    Ok(lexpr::from_str(&quote_units(text).0)?)
+}
+
+/// Call `func` with each key and value of a property list like `(:freq 1000 :ch (0 1))`.
+/// The leading colon on keys is optional.
+pub fn for_plist<F>(plist: &Value, mut func: F) -> Result<()>
+    where F: FnMut(&str, &Value) -> Result<()>
+{
+    let mut i = plist.list_iter().ok_or_else(|| anyhow!("expected a list of keys and values, got {}", plist))?;
+    while let Some(key) = i.next() {
+        let Some(name) = key.as_symbol() else {
+            return Err(anyhow!("expected a key, got {}", key));
+        };
+        let name = name.strip_prefix(':').unwrap_or(name);
+        let Some(val) = i.next() else {
+            return Err(anyhow!("{}: missing value", name));
+        };
+        func(name, val).map_err(|e| anyhow!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// A plain number, or a frequency or time with its unit (Hz, kHz, s, ms).
+pub fn as_f32(val: &Value) -> Result<f32> {
+    match quantity(val)? {
+        (x, "" | "Hz" | "s") => Ok(x),
+        (x, "kHz") => Ok(x * 1000.0),
+        (x, "ms") => Ok(x / 1000.0),
+        (_, unit) => Err(anyhow!("{} is not a frequency or time", unit)),
+    }
+}
+
+/// A level in dB. Plain numbers are taken as dB, percentages are converted.
+pub fn as_db(val: &Value) -> Result<f32> {
+    match quantity(val)? {
+        (x, "" | "dB" | "dBFS") => Ok(x),
+        (x, "%") => Ok(crate::spectrum::to_db(x / 100.0)),
+        (_, unit) => Err(anyhow!("{} is not a level", unit)),
+    }
+}
+
+/// A gain factor, or an amplitude relative to full scale. Plain numbers are
+/// linear; dB, dBFS and percentages are converted.
+pub fn as_gain(val: &Value) -> Result<f32> {
+    match quantity(val)? {
+        (x, "") => Ok(x),
+        (x, "dB" | "dBFS") => Ok(10f32.powf(x / 20.0)),
+        (x, "%") => Ok(x / 100.0),
+        (_, unit) => Err(anyhow!("{} is not a gain", unit)),
+    }
+}
+
+pub fn as_usize(val: &Value) -> Result<usize> {
+    val.as_u64().map(|v| v as usize).ok_or_else(|| anyhow!("expected a whole number, got {}", val))
+}
+
+/// #t or #f.
+pub fn as_bool(val: &Value) -> Result<bool> {
+    val.as_bool().ok_or_else(|| anyhow!("expected #t or #f, got {}", val))
+}
+
+/// A symbol or a string.
+pub fn as_name(val: &Value) -> Result<&str> {
+    val.as_symbol().or(val.as_str()).ok_or_else(|| anyhow!("expected a name, got {}", val))
+}
+
+/// A single channel number.
+pub fn as_channel(val: &Value) -> Result<u8> {
+    u8::try_from(as_usize(val)?).map_err(|_| anyhow!("channel {} out of range", val))
+}
+
+/// A single channel number or a list of them.
+pub fn as_channels(val: &Value) -> Result<Vec<u8>> {
+    let to_channel = |v: &Value| -> Result<u8> {
+        v.as_u64().and_then(|c| u8::try_from(c).ok()).ok_or_else(|| anyhow!("expected a channel number, got {}", v))
+    };
+    match val.list_iter() {
+        Some(i) => i.map(to_channel).collect(),
+        None => Ok(vec![to_channel(val)?]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the commands `text` runs, as "name args"
+    fn run(text: &str) -> Result<Vec<String>> {
+        let mut calls = Vec::new();
+        Script::from_str("test", text)?.run(|name, args| {
+            calls.push(format!("{name} {args}"));
+            Ok(())
+        })?;
+        Ok(calls)
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let calls = run("(set freq 1000)\n(for g (1 -3dB) (sinout :freq $freq :g $g :x (g $g)))").unwrap();
+        assert_eq!(calls, [
+            "sinout (:freq 1000 :g 1 :x (g 1))",
+            "sinout (:freq 1000 :g \"-3dB\" :x (g \"-3dB\"))",
+        ]);
+    }
+
+    #[test]
+    fn unset_variables_are_errors() {
+        assert_eq!(run("(sinout :freq $f)").unwrap_err().to_string(), "test:1:1: sinout: f is not set");
+    }
+
+    #[test]
+    fn error_columns_are_as_written() {
+        // quoting 1kHz adds two characters ahead of the error, but only on its own line
+        let error = |text| run(text).unwrap_err().to_string();
+        assert_eq!(error("(x 1kHz) (y $nope)"), "test:1:10: y: nope is not set");
+        assert_eq!(error("(x 1kHz)\n(y $nope)"), "test:2:1: y: nope is not set");
+        assert_eq!(error("(x 1kHz) )"), "test:1:10: expected value");
+    }
+}
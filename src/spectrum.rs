@@ -0,0 +1,120 @@
+use rustfft::{FftPlanner, num_complex::Complex};
+use anyhow::{anyhow, Result};
+
+/// dBFS value used for bins with no energy, so plots and comparisons stay finite.
+pub const DB_FLOOR: f32 = -200.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Window {
+    Rect,
+    #[default]
+    Hann,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl Window {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "rect" => Ok(Window::Rect),
+            "hann" => Ok(Window::Hann),
+            "blackman-harris" | "bh" => Ok(Window::BlackmanHarris),
+            "flat-top" | "flattop" => Ok(Window::FlatTop),
+            _ => Err(anyhow!("unknown window {}", name))
+        }
+    }
+
+    // cosine-sum coefficients a0, a1, ... ; w[n] = sum (-1)^k a_k cos(2 pi k n / N)
+    fn coefs(&self) -> &'static [f32] {
+        match self {
+            Window::Rect => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[0.215_578_95, 0.416_631_6, 0.277_263_2, 0.083_578_95, 0.006_947_368],
+        }
+    }
+
+    /// Periodic window of length `n`.
+    pub fn coefficients(&self, n: usize) -> Vec<f32> {
+        let coefs = self.coefs();
+        (0..n).map(|i| {
+            let x = 2.0 * std::f32::consts::PI * (i as f32) / (n as f32);
+            coefs.iter().enumerate().fold(0.0, |w, (k, a)| {
+                let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                w + sign * a * (x * k as f32).cos()
+            })
+        }).collect()
+    }
+
+    /// Equivalent noise bandwidth in bins. Summing the power of the bins under a
+    /// tone overstates it by this factor.
+    pub fn enbw(&self, n: usize) -> f32 {
+        let w = self.coefficients(n);
+        let sum: f32 = w.iter().sum();
+        let sum_sq: f32 = w.iter().map(|x| x * x).sum();
+        (n as f32) * sum_sq / (sum * sum)
+    }
+}
+
+pub fn to_db(x: f32) -> f32 {
+    if x > 0.0 { (20.0 * x.log10()).max(DB_FLOOR) } else { DB_FLOOR }
+}
+
+/// Windowed amplitude spectrum of `samples`.
+/// Returns linear peak amplitudes for bins 0..=n/2, scaled so a full-scale sine reads 1.0.
+pub fn amplitude_spectrum(samples: &[f32], window: Window) -> Vec<f32> {
+    let n = samples.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let w = window.coefficients(n);
+    let gain: f32 = w.iter().sum();
+    let mut buf: Vec<Complex<f32>> = samples.iter().zip(&w)
+        .map(|(s, w)| Complex::new(s * w, 0.0))
+        .collect();
+    let mut planner = FftPlanner::new();
+    planner.plan_fft_forward(n).process(&mut buf);
+    buf[..=n / 2].iter().enumerate()
+        .map(|(i, c)| {
+            // dc and nyquist are not mirrored, so they don't get the factor of 2
+            let scale = if i == 0 || i == n / 2 { 1.0 } else { 2.0 };
+            scale * c.norm() / gain
+        })
+        .collect()
+}
+
+/// Windowed magnitude spectrum as (frequency in Hz, level in dBFS) points, excluding dc.
+pub fn spectrum(samples: &[f32], sample_rate: f32, window: Window) -> Vec<(f32, f32)> {
+    let n = samples.len();
+    amplitude_spectrum(samples, window).iter().enumerate()
+        .skip(1)
+        .map(|(i, a)| ((i as f32) * sample_rate / (n as f32), to_db(*a)))
+        .collect()
+}
+
+/// Linear convolution of `a` and `b` via FFT.
+pub fn convolve(a: &[f32], b: &[f32]) -> Vec<f32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let out_len = a.len() + b.len() - 1;
+    let n = out_len.next_power_of_two();
+    let mut planner = FftPlanner::<f32>::new();
+    let fwd = planner.plan_fft_forward(n);
+    let inv = planner.plan_fft_inverse(n);
+
+    let pad = |v: &[f32]| {
+        let mut c: Vec<Complex<f32>> = v.iter().map(|s| Complex::new(*s, 0.0)).collect();
+        c.resize(n, Complex::new(0.0, 0.0));
+        c
+    };
+    let mut fa = pad(a);
+    let mut fb = pad(b);
+    fwd.process(&mut fa);
+    fwd.process(&mut fb);
+    for (x, y) in fa.iter_mut().zip(&fb) {
+        *x *= y;
+    }
+    inv.process(&mut fa);
+    fa[..out_len].iter().map(|c| c.re / n as f32).collect()
+}
@@ -0,0 +1,172 @@
+// Exponential sine sweep measurement, after A. Farina, "Simultaneous measurement
+// of impulse response and distortion with a swept-sine technique" (AES 2000).
+
+use rustfft::{FftPlanner, num_complex::Complex};
+use crate::spectrum;
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sweep {
+    pub start: f32,
+    pub end: f32,
+    pub dur: f32,
+    pub sample_rate: f32,
+}
+
+impl Sweep {
+    pub fn new(start: f32, end: f32, dur: f32, sample_rate: f32) -> Self {
+        Self { start, end, dur, sample_rate }
+    }
+
+    fn len(&self) -> usize {
+        (self.dur * self.sample_rate) as usize
+    }
+
+    // ln(f2/f1)
+    fn rate(&self) -> f64 {
+        (self.end as f64 / self.start as f64).ln()
+    }
+
+    /// The sweep itself, at unit amplitude.
+    pub fn samples(&self) -> Vec<f32> {
+        let t_total = self.dur as f64;
+        let r = self.rate();
+        let k = 2.0 * PI * self.start as f64 * t_total / r;
+        (0..self.len())
+            .map(|i| {
+                let t = i as f64 / self.sample_rate as f64;
+                (k * ((t * r / t_total).exp() - 1.0)).sin() as f32
+            })
+            .collect()
+    }
+
+    /// Time-reversed sweep with a -6 dB/octave envelope, scaled so that
+    /// convolving it with the sweep gives unity gain in the middle of the band.
+    pub fn inverse(&self) -> Vec<f32> {
+        let x = self.samples();
+        let n = x.len();
+        let l = self.dur as f64 / self.rate();
+        let inv: Vec<f64> = (0..n)
+            .map(|i| x[n - 1 - i] as f64 * (-(i as f64) / self.sample_rate as f64 / l).exp())
+            .collect();
+
+        // gain of sweep * inverse at the geometric centre frequency
+        let fc = (self.start as f64 * self.end as f64).sqrt();
+        let w = 2.0 * PI * fc / self.sample_rate as f64;
+        let dft = |v: &mut dyn Iterator<Item = f64>| {
+            v.enumerate().fold(Complex::new(0.0, 0.0), |acc, (i, s)| {
+                acc + Complex::from_polar(s, -w * i as f64)
+            })
+        };
+        let gain = (dft(&mut x.iter().map(|s| *s as f64)) * dft(&mut inv.iter().copied())).norm();
+
+        inv.iter().map(|s| (s / gain) as f32).collect()
+    }
+
+    /// Seconds by which the impulse response of harmonic `k` precedes the linear one.
+    pub fn harmonic_delay(&self, k: usize) -> f32 {
+        (self.dur as f64 * (k as f64).ln() / self.rate()) as f32
+    }
+}
+
+pub struct SweepResult {
+    /// linear impulse response, starting `pre` samples before its peak
+    pub ir: Vec<f32>,
+    /// impulse responses of harmonics 2, 3, ...
+    pub harmonics: Vec<Vec<f32>>,
+    /// samples of `ir` ahead of the peak
+    pub pre: usize,
+    /// sample index of the linear response peak in the recording (i.e. the delay)
+    pub delay: usize,
+}
+
+/// Deconvolve `recording` of `sweep` and split out the linear and harmonic
+/// impulse responses. `ir_len` is the length of the linear IR in samples.
+pub fn analyze(sweep: &Sweep, recording: &[f32], harmonics: usize, ir_len: usize) -> SweepResult {
+    let inverse = sweep.inverse();
+    let h = spectrum::convolve(recording, &inverse);
+
+    // zero lag is at inverse.len() - 1; the linear response is the largest peak after it
+    let zero = inverse.len() - 1;
+    let peak = h.iter().enumerate()
+        .skip(zero)
+        .fold((zero, 0.0f32), |best, (i, s)| if s.abs() > best.1 { (i, s.abs()) } else { best })
+        .0;
+
+    let sr = sweep.sample_rate;
+    let centre = |k: usize| peak as f32 - sweep.harmonic_delay(k) * sr;
+    let slice = |start: f32, len: usize| -> Vec<f32> {
+        let start = start.round() as isize;
+        (0..len as isize)
+            .map(|i| {
+                let j = start + i;
+                if j >= 0 && (j as usize) < h.len() { h[j as usize] } else { 0.0 }
+            })
+            .collect()
+    };
+
+    // each harmonic response gets the gap between it and the next lower one,
+    // starting a tenth of that gap early
+    let pre = if harmonics >= 2 {
+        ((centre(1) - centre(2)) / 10.0) as usize
+    } else {
+        (0.001 * sr) as usize
+    };
+    let ir = slice(peak as f32 - pre as f32, ir_len);
+    let harmonic_irs = (2..=harmonics)
+        .map(|k| {
+            let gap = centre(k - 1) - centre(k);
+            slice(centre(k) - gap / 10.0, gap as usize)
+        })
+        .collect();
+
+    SweepResult {
+        ir,
+        harmonics: harmonic_irs,
+        pre,
+        delay: peak - zero,
+    }
+}
+
+/// Frequency response of an impulse response whose reference point is `pre`
+/// samples in, as (frequency in Hz, magnitude in dB, phase in degrees).
+pub fn frequency_response(ir: &[f32], pre: usize, sample_rate: f32) -> Vec<(f32, f32, f32)> {
+    let n = ir.len().next_power_of_two();
+    let mut buf: Vec<Complex<f32>> = ir.iter().map(|s| Complex::new(*s, 0.0)).collect();
+    buf.resize(n, Complex::new(0.0, 0.0));
+    FftPlanner::new().plan_fft_forward(n).process(&mut buf);
+    buf[1..=n / 2].iter().enumerate()
+        .map(|(i, c)| {
+            let bin = i + 1;
+            // undo the linear phase of starting `pre` samples early
+            let shift = Complex::from_polar(1.0, 2.0 * std::f32::consts::PI * (bin * pre) as f32 / n as f32);
+            let c = c * shift;
+            let f = bin as f32 * sample_rate / n as f32;
+            (f, spectrum::to_db(c.norm()), c.arg().to_degrees())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_deconvolves_to_a_unit_impulse() {
+        let fs = 48000.0;
+        let sweep = Sweep::new(20.0, 20000.0, 2.0, fs);
+        // through a plain 100 sample delay, with room for the tail
+        let mut recording = vec![0.0; 100];
+        recording.extend(sweep.samples());
+        recording.resize(recording.len() + fs as usize / 2, 0.0);
+
+        let result = analyze(&sweep, &recording, 1, 4096);
+        assert_eq!(result.delay, 100);
+        // flat and without phase shift, well inside the swept band
+        for (f, db, phase) in frequency_response(&result.ir, result.pre, fs) {
+            if (100.0..=10000.0).contains(&f) {
+                assert!(db.abs() < 0.5 && phase.abs() < 5.0, "{f} Hz: {db:.2} dB {phase:.1} deg");
+            }
+        }
+    }
+}